@@ -0,0 +1,178 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use ::Lz77Options;
+use decompressor::{Decompressor, Status};
+
+/// Size of the chunks read from the inner reader.
+const INPUT_BUFFER_SIZE: usize = 1 << 14;
+
+/// Decodes a stream read from the async reader `R`, using a `Decompressor`. Accepts the same
+/// streams and options as `Lz77Decoder`.
+///
+/// The inner reader is read in chunks, so it may be read past the end of the stream.
+pub struct AsyncLz77Decoder<R>
+    where R: AsyncRead + Unpin
+{
+    inner: R,
+    decompressor: Decompressor,
+    input: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R> AsyncLz77Decoder<R>
+    where R: AsyncRead + Unpin
+{
+    /// Creates a decoder reading from `reader`, see `Lz77Decoder::new` for how `options` are
+    /// used.
+    pub fn new(reader: R, options: Lz77Options) -> Self {
+        AsyncLz77Decoder {
+            inner: reader,
+            decompressor: Decompressor::new(options),
+            input: Vec::with_capacity(INPUT_BUFFER_SIZE),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly will corrupt the compressed stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads the next chunk of input once the previous one is used up.
+    fn poll_fill_input(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.pos < self.input.len() || self.eof {
+            return Poll::Ready(Ok(()));
+        }
+        self.input.resize(INPUT_BUFFER_SIZE, 0);
+        self.pos = 0;
+        let res = {
+            let mut buf = ReadBuf::new(&mut self.input[..]);
+            Pin::new(&mut self.inner).poll_read(cx, &mut buf).map_ok(|()| buf.filled().len())
+        };
+        match res {
+            Poll::Ready(Ok(n)) => {
+                self.input.truncate(n);
+                self.eof = n == 0;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => {
+                self.input.clear();
+                Poll::Ready(Err(e))
+            }
+            Poll::Pending => {
+                self.input.clear();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R> AsyncRead for AsyncLz77Decoder<R>
+    where R: AsyncRead + Unpin
+{
+    fn poll_read(self: Pin<&mut Self>,
+                 cx: &mut Context,
+                 buf: &mut ReadBuf)
+                 -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut written = 0;
+        loop {
+            if !this.decompressor.is_done() {
+                match this.poll_fill_input(cx) {
+                    Poll::Ready(Ok(())) => {}
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    // The bytes decoded so far are handed out without waiting for more input.
+                    Poll::Pending if written > 0 => return Poll::Ready(Ok(())),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            let (consumed, produced, status) =
+                this.decompressor.decompress(&this.input[this.pos..], buf.initialize_unfilled());
+            this.pos += consumed;
+            buf.advance(produced);
+            written += produced;
+            match status {
+                Status::Done | Status::OutputFull => return Poll::Ready(Ok(())),
+                // The bytes decoded before the error are handed out first.
+                Status::Failed(_) if written > 0 => return Poll::Ready(Ok(())),
+                Status::Failed(err) => return Poll::Ready(Err(err.into())),
+                Status::NeedsInput if this.eof => {
+                    return Poll::Ready(this.decompressor.finish().map_err(io::Error::from));
+                }
+                Status::NeedsInput => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncLz77Decoder;
+    use std::cmp;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use tokio::io::{duplex, AsyncRead, AsyncWrite, ReadBuf};
+    use ::{compress, Lz77Options};
+
+    /// Decodes `data` written in small pieces into an in-memory pipe, writing more whenever the
+    /// decoder has to wait.
+    fn decode(data: &[u8], opts: Lz77Options) -> io::Result<Vec<u8>> {
+        let (mut writer, reader) = duplex(37);
+        let mut decoder = AsyncLz77Decoder::new(reader, opts);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut input = data;
+        let mut output = Vec::new();
+        loop {
+            let mut buf = [0u8; 100];
+            let mut buf = ReadBuf::new(&mut buf);
+            match Pin::new(&mut decoder).poll_read(&mut cx, &mut buf) {
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => return Ok(output),
+                Poll::Ready(Ok(())) => output.extend_from_slice(buf.filled()),
+                Poll::Ready(Err(e)) => return Err(e),
+                Poll::Pending if input.is_empty() => {
+                    assert!(Pin::new(&mut writer).poll_shutdown(&mut cx).is_ready());
+                }
+                Poll::Pending => {
+                    let chunk = &input[..cmp::min(13, input.len())];
+                    match Pin::new(&mut writer).poll_write(&mut cx, chunk) {
+                        Poll::Ready(n) => input = &input[n.unwrap()..],
+                        Poll::Pending => panic!("Decoder waits for input while the pipe is full"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_decoder() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i * i / 7 % 251) as u8).collect();
+        let options = [Lz77Options::default(),
+                       Lz77Options { huffman: true, checksum: true, ..Default::default() },
+                       Lz77Options { window_bits: 20, ..Default::default() }];
+        for opts in &options {
+            let compressed = compress(&data, opts);
+            assert_eq!(decode(&compressed, opts.clone()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_async_decoder_truncated() {
+        let data = b"a stream that ends before its end marker, a stream that ends early";
+        let compressed = compress(data, &Lz77Options::default());
+        let err = decode(&compressed[..compressed.len() - 2], Lz77Options::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,256 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+use ::Lz77Options;
+use searcher::Searcher;
+use compressor::{Compressor, FlushMode};
+use decompressor::Status;
+use errors::Error;
+
+/// Size of the chunks written to the inner writer.
+const OUTPUT_BUFFER_SIZE: usize = 1 << 14;
+
+/// Encodes everything written to it into the async writer `W`, using a `Compressor`. Produces
+/// the same stream as `Lz77Encoder`.
+///
+/// The stream is only terminated by `poll_shutdown`, dropping the encoder leaves it
+/// incomplete.
+pub struct AsyncLz77Encoder<W, S>
+    where W: AsyncWrite + Unpin,
+          S: Searcher + Default
+{
+    inner: W,
+    compressor: Compressor<S>,
+    /// Compressed bytes not written to `inner` yet, from `pos` to `end`.
+    buf: Vec<u8>,
+    pos: usize,
+    end: usize,
+}
+
+impl<W, S> AsyncLz77Encoder<W, S>
+    where W: AsyncWrite + Unpin,
+          S: Searcher + Default
+{
+    /// Creates an encoder with a default searcher tuned by `options`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
+    pub fn new(output: W, options: Lz77Options) -> Self {
+        let mut searcher = S::default();
+        searcher.configure(&options);
+        Self::with_searcher(output, options, searcher)
+    }
+
+    /// Creates an encoder using the given searcher instance. The searcher is reset, but keeps
+    /// its tuning parameters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
+    pub fn with_searcher(output: W, options: Lz77Options, searcher: S) -> Self {
+        AsyncLz77Encoder {
+            inner: output,
+            compressor: Compressor::with_searcher(options, searcher),
+            buf: vec![0; OUTPUT_BUFFER_SIZE],
+            pos: 0,
+            end: 0,
+        }
+    }
+
+    /// Returns the inner writer without writing pending data or the stream trailer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writing to the inner writer directly will corrupt the compressed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Writes the buffered output to the inner writer.
+    fn poll_write_output(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.pos < self.end {
+            let output = &self.buf[self.pos..self.end];
+            let n = match Pin::new(&mut self.inner).poll_write(cx, output) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::WriteZero,
+                                                      "Failed to write compressed data")));
+            }
+            self.pos += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Flushes the compressor as requested by `flush` and writes its output, until it stops
+    /// with `until` without producing any more.
+    fn poll_compress(&mut self,
+                     cx: &mut Context,
+                     flush: FlushMode,
+                     until: Status)
+                     -> Poll<io::Result<()>> {
+        loop {
+            match self.poll_write_output(cx) {
+                Poll::Ready(Ok(())) => {}
+                other => return other,
+            }
+            let (_, produced, status) = self.compressor.compress(&[], &mut self.buf, flush);
+            self.pos = 0;
+            self.end = produced;
+            if produced == 0 && status == until {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<W, S> AsyncWrite for AsyncLz77Encoder<W, S>
+    where W: AsyncWrite + Unpin,
+          S: Searcher + Default + Unpin
+{
+    fn poll_write(self: Pin<&mut Self>,
+                  cx: &mut Context,
+                  input: &[u8])
+                  -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match this.poll_write_output(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            let (consumed, produced, status) =
+                this.compressor.compress(input, &mut this.buf, FlushMode::None);
+            this.pos = 0;
+            this.end = produced;
+            match status {
+                Status::Done => return Poll::Ready(Err(Error::StreamFinished.into())),
+                Status::Failed(err) => return Poll::Ready(Err(err.into())),
+                _ => {}
+            }
+            // Output that did not fit is written by the next call, before taking more input.
+            if consumed > 0 || input.is_empty() {
+                return Poll::Ready(Ok(consumed));
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_compress(cx, FlushMode::Sync, Status::NeedsInput) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    /// Writes all pending data followed by the end marker and trailer, then shuts down the
+    /// inner writer. No more data can be written afterwards.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_compress(cx, FlushMode::Finish, Status::Done) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncLz77Encoder;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use tokio::io::{duplex, AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+    use searcher::AnySearcher;
+    use decompressor::{Decompressor, Status};
+    use test_util::sample_data;
+    use ::{compress, decompress, Lz77Options};
+
+    /// Moves the bytes available in `reader` to `out`, returning how many there were.
+    fn read_available(reader: &mut DuplexStream, out: &mut Vec<u8>, cx: &mut Context) -> usize {
+        let mut total = 0;
+        loop {
+            let mut buf = [0u8; 50];
+            let mut buf = ReadBuf::new(&mut buf);
+            match Pin::new(&mut *reader).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(())) if !buf.filled().is_empty() => {
+                    out.extend_from_slice(buf.filled());
+                    total += buf.filled().len();
+                }
+                Poll::Ready(Err(e)) => panic!("{}", e),
+                _ => return total,
+            }
+        }
+    }
+
+    /// Writes `data` through an encoder into a small in-memory pipe, reading its other end
+    /// whenever the encoder has to wait. With `flush` set, checks that every flush makes
+    /// everything written so far decodable.
+    fn encode(data: &[u8], opts: &Lz77Options, flush: bool) -> Vec<u8> {
+        let (writer, mut reader) = duplex(64);
+        let mut encoder = AsyncLz77Encoder::<_, AnySearcher>::new(writer, opts.clone());
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut compressed = Vec::new();
+        let mut written = 0;
+        for chunk in data.chunks(1000) {
+            let mut chunk = chunk;
+            while !chunk.is_empty() {
+                match Pin::new(&mut encoder).poll_write(&mut cx, chunk) {
+                    Poll::Ready(n) => chunk = &chunk[n.unwrap()..],
+                    Poll::Pending => {
+                        read_available(&mut reader, &mut compressed, &mut cx);
+                    }
+                }
+            }
+            written += 1000;
+            if flush {
+                while Pin::new(&mut encoder).poll_flush(&mut cx).map(Result::unwrap).is_pending() {
+                    read_available(&mut reader, &mut compressed, &mut cx);
+                }
+                read_available(&mut reader, &mut compressed, &mut cx);
+                let end = ::std::cmp::min(written, data.len());
+                let mut output = vec![0; data.len()];
+                let (consumed, decoded, status) =
+                    Decompressor::new(opts.clone()).decompress(&compressed, &mut output);
+                assert_eq!((consumed, status), (compressed.len(), Status::NeedsInput));
+                assert_eq!(&output[..decoded], &data[..end]);
+            }
+        }
+        while Pin::new(&mut encoder).poll_shutdown(&mut cx).map(Result::unwrap).is_pending() {
+            read_available(&mut reader, &mut compressed, &mut cx);
+        }
+        while read_available(&mut reader, &mut compressed, &mut cx) > 0 {}
+        let res = Pin::new(&mut encoder).poll_write(&mut cx, b"more");
+        assert!(matches!(res, Poll::Ready(Err(_))));
+        compressed
+    }
+
+    #[test]
+    fn test_async_encoder() {
+        let data = sample_data(20000);
+        let options = [Lz77Options::default(),
+                       Lz77Options { huffman: true, checksum: true, ..Default::default() },
+                       Lz77Options { window_bits: 20, ..Default::default() }];
+        for opts in &options {
+            let compressed = encode(&data, opts, false);
+            assert_eq!(compressed, compress(&data, opts));
+            assert_eq!(decompress(&compressed, opts).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_async_encoder_flush() {
+        let data = sample_data(5500);
+        let opts = Lz77Options::default();
+        let compressed = encode(&data, &opts, true);
+        assert_eq!(decompress(&compressed, &opts).unwrap(), data);
+    }
+}
@@ -3,10 +3,14 @@ extern crate clap;
 
 mod util;
 
-use std::fs::File;
+use std::fs::{self, File};
+use std::process;
 use std::io::{copy, BufReader, BufWriter, Write};
-use lz77::{Lz77Encoder, LinearSearcher, Lz77Options};
-use clap::{App, Arg};
+use lz77::{Lz77Encoder, ParallelLz77Encoder, AnySearcher, SearcherKind, Lz77Options,
+           DEFAULT_LEVEL};
+use clap::{App, Arg, ArgGroup};
+
+const LEVELS: [&str; 9] = ["1", "2", "3", "4", "5", "6", "7", "8", "9"];
 
 fn main() {
     let matches = App::new("Lz77 compressor")
@@ -21,13 +25,93 @@ fn main() {
             .required(true)
             .help("Sets the output file to use")
             .index(2))
-        .arg(Arg::with_name("window_size")
+        .arg(Arg::with_name("window_bits")
             .default_value("12")
             .short("w")
-            .long("window"))
+            .long("window")
+            .help("Sets the window size in bits (9-24), larger windows need longer codewords"))
+        .args(&LEVELS.iter()
+            .map(|level| Arg::with_name(level).short(level).hidden(true))
+            .collect::<Vec<_>>())
+        .group(ArgGroup::with_name("level").args(&LEVELS))
+        .arg(Arg::with_name("searcher")
+            .takes_value(true)
+            .possible_values(&["linear", "hashchain", "binarytree", "suffixarray"])
+            .short("s")
+            .long("searcher")
+            .help("Sets the match finder to use, overriding the level. suffixarray needs threads \
+                   and falls back to binarytree otherwise"))
+        .arg(Arg::with_name("max_chain")
+            .takes_value(true)
+            .short("c")
+            .long("chain")
+            .help("Sets the maximum hash chain depth, overriding the level"))
+        .arg(Arg::with_name("lazy")
+            .takes_value(true)
+            .short("l")
+            .long("lazy")
+            .help("Sets how many positions are checked for a better match (0-2), overriding \
+                   the level"))
+        .arg(Arg::with_name("optimal")
+            .long("optimal")
+            .help("Chooses the tokens by their encoded size instead of greedily, which is \
+                   slower"))
+        .arg(Arg::with_name("min_match")
+            .takes_value(true)
+            .long("min-match")
+            .help("Sets the shortest match to encode, by default the shortest one that is not \
+                   larger than the literals it replaces"))
+        .arg(Arg::with_name("max_match")
+            .takes_value(true)
+            .long("max-match")
+            .help("Sets the longest match to encode, longer matches need longer codewords"))
+        .arg(Arg::with_name("raw")
+            .short("r")
+            .long("raw")
+            .help("Writes the headerless format"))
+        .arg(Arg::with_name("no_checksum")
+            .long("no-checksum")
+            .help("Omits the content checksum from the stream trailer"))
+        .arg(Arg::with_name("huffman")
+            .long("huffman")
+            .help("Entropy-codes the tokens with per-block Huffman tables"))
+        .arg(Arg::with_name("block_size")
+            .takes_value(true)
+            .short("b")
+            .long("block-size")
+            .help("Splits the input into independently decodable blocks of this many bytes"))
+        .arg(Arg::with_name("block_checksum")
+            .long("block-checksum")
+            .requires("block_size")
+            .help("Adds a checksum of its content to every block"))
+        .arg(Arg::with_name("linked_blocks")
+            .long("linked-blocks")
+            .requires("block_size")
+            .help("Lets blocks refer back into the previous ones, so they can only be decoded \
+                   in order"))
+        .arg(Arg::with_name("block_index")
+            .long("block-index")
+            .requires("block_size")
+            .conflicts_with("linked_blocks")
+            .help("Appends an index of the blocks, so the content can be read at any offset"))
+        .arg(Arg::with_name("threads")
+            .takes_value(true)
+            .short("t")
+            .long("threads")
+            .conflicts_with("raw")
+            .help("Compresses blocks on this many threads, 0 for one per CPU. Splits the input \
+                   into blocks of 1 MiB unless --block-size is given"))
+        .arg(Arg::with_name("dictionary")
+            .takes_value(true)
+            .short("D")
+            .long("dictionary")
+            .conflicts_with("threads")
+            .help("Primes the window with the content of this file, which decompressing needs \
+                   as well"))
         .arg(Arg::with_name("verbose")
             .short("v")
             .help("Sets verbose output"))
+        .after_help("Use -1 (fastest) to -9 (smallest output) to set the compression level.")
         .get_matches();
 
     let infile = matches.value_of("INPUT")
@@ -36,17 +120,70 @@ fn main() {
     let outfile = matches.value_of("OUTPUT")
         .and_then(|file| File::create(file).ok())
         .expect("Output: Could not create file");
-    let window_size: u8 = matches.value_of("window_size")
+    let window_bits: u8 = matches.value_of("window_bits")
         .and_then(|size| size.parse::<u8>().ok())
-        .expect("Invalid value for window_size");
+        .expect("Invalid value for window_bits");
+    let level = LEVELS.iter()
+        .position(|level| matches.is_present(level))
+        .map_or(DEFAULT_LEVEL, |idx| idx as u8 + 1);
 
     let mut read = util::StatsReader::new(BufReader::new(infile));
     let mut write = util::StatsWriter::new(BufWriter::new(outfile));
 
-    let opts = Lz77Options { window_size: window_size };
-    {
-        let mut encoder = Lz77Encoder::<_, LinearSearcher>::new(&mut write, opts);
+    let mut opts = Lz77Options {
+        window_bits,
+        raw: matches.is_present("raw"),
+        checksum: !matches.is_present("no_checksum"),
+        huffman: matches.is_present("huffman"),
+        optimal: matches.is_present("optimal"),
+        block_checksum: matches.is_present("block_checksum"),
+        linked_blocks: matches.is_present("linked_blocks"),
+        block_index: matches.is_present("block_index"),
+        ..Lz77Options::with_level(level)
+    };
+    match matches.value_of("searcher") {
+        Some("linear") => opts.searcher = SearcherKind::Linear,
+        Some("hashchain") => opts.searcher = SearcherKind::HashChain,
+        Some("binarytree") => opts.searcher = SearcherKind::BinaryTree,
+        Some("suffixarray") => opts.searcher = SearcherKind::SuffixArray,
+        Some(searcher) => unreachable!("Unknown searcher {}", searcher),
+        None => {}
+    }
+    if let Some(max_chain) = matches.value_of("max_chain") {
+        opts.max_chain = max_chain.parse::<usize>().expect("Invalid value for max_chain");
+    }
+    if let Some(lazy) = matches.value_of("lazy") {
+        opts.lazy = lazy.parse::<u8>().expect("Invalid value for lazy");
+    }
+    if let Some(min_match) = matches.value_of("min_match") {
+        opts.min_match_len = Some(min_match.parse::<usize>().expect("Invalid value for min_match"));
+    }
+    if let Some(max_match) = matches.value_of("max_match") {
+        opts.max_match_len = Some(max_match.parse::<usize>().expect("Invalid value for max_match"));
+    }
+    if let Some(block_size) = matches.value_of("block_size") {
+        opts.block_size = Some(block_size.parse::<usize>().expect("Invalid value for block_size"));
+    }
+    if let Err(e) = opts.validate() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    let dictionary = matches.value_of("dictionary")
+        .map(|file| fs::read(file).expect("Dictionary: No such file"));
+    if let Some(threads) = matches.value_of("threads") {
+        let threads = threads.parse::<usize>().expect("Invalid value for threads");
+        let mut encoder = ParallelLz77Encoder::new(&mut write, opts, threads);
+        copy(&mut read, &mut encoder).expect("Something went wrong while encoding");
+        encoder.finish().expect("Something went wrong while finishing the stream");
+    } else {
+        let mut encoder = match dictionary {
+            Some(ref dictionary) => {
+                Lz77Encoder::<_, AnySearcher>::with_dictionary(&mut write, opts, dictionary)
+            }
+            None => Lz77Encoder::<_, AnySearcher>::new(&mut write, opts),
+        };
         copy(&mut read, &mut encoder).expect("Something went wrong while encoding");
+        encoder.finish().expect("Something went wrong while finishing the stream");
     }
     if matches.is_present("verbose") {
         write.flush().expect("Flush failed");
@@ -3,9 +3,9 @@ extern crate clap;
 
 mod util;
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{copy, BufReader, BufWriter, Write};
-use lz77::{Lz77Decoder, Lz77Options};
+use lz77::{Lz77Decoder, ParallelLz77Decoder, Lz77Options};
 use clap::{Arg, App};
 
 fn main() {
@@ -21,10 +21,32 @@ fn main() {
             .required(true)
             .help("Sets the output file to use")
             .index(2))
-        .arg(Arg::with_name("window_size")
+        .arg(Arg::with_name("window_bits")
             .default_value("12")
             .short("w")
-            .long("window"))
+            .long("window")
+            .help("Sets the window size for raw streams, otherwise it is read from the header"))
+        .arg(Arg::with_name("raw")
+            .short("r")
+            .long("raw")
+            .help("Reads the headerless format"))
+        .arg(Arg::with_name("max_size")
+            .takes_value(true)
+            .short("m")
+            .long("max-size")
+            .help("Fails if the decompressed output would exceed this many bytes"))
+        .arg(Arg::with_name("threads")
+            .takes_value(true)
+            .short("t")
+            .long("threads")
+            .help("Decodes independent blocks on this many threads, 0 for one per CPU"))
+        .arg(Arg::with_name("dictionary")
+            .takes_value(true)
+            .short("D")
+            .long("dictionary")
+            .conflicts_with("threads")
+            .help("Primes the window with the content of this file, which has to be the \
+                   dictionary the input was compressed with"))
         .arg(Arg::with_name("verbose")
             .short("v")
             .help("Sets verbose output"))
@@ -36,16 +58,31 @@ fn main() {
     let outfile = matches.value_of("OUTPUT")
         .and_then(|file| File::create(file).ok())
         .expect("Output: Could not create file");
-    let window_size: u8 = matches.value_of("window_size")
+    let window_bits: u8 = matches.value_of("window_bits")
         .and_then(|size| size.parse::<u8>().ok())
-        .expect("Invalid value for window_size");
+        .expect("Invalid value for window_bits");
 
     let mut read = util::StatsReader::new(BufReader::new(infile));
     let mut write = util::StatsWriter::new(BufWriter::new(outfile));
 
-    let opts = Lz77Options { window_size: window_size };
-    {
-        let mut decoder = Lz77Decoder::new(&mut read, opts);
+    let opts = Lz77Options {
+        window_bits,
+        raw: matches.is_present("raw"),
+        max_output_size: matches.value_of("max_size")
+            .map(|size| size.parse::<u64>().expect("Invalid value for max_size")),
+        ..Default::default()
+    };
+    let dictionary = matches.value_of("dictionary")
+        .map(|file| fs::read(file).expect("Dictionary: No such file"));
+    if let Some(threads) = matches.value_of("threads") {
+        let threads = threads.parse::<usize>().expect("Invalid value for threads");
+        let mut decoder = ParallelLz77Decoder::new(&mut read, opts, threads);
+        copy(&mut decoder, &mut write).expect("Something went wrong while decoding");
+    } else {
+        let mut decoder = match dictionary {
+            Some(ref dictionary) => Lz77Decoder::with_dictionary(&mut read, opts, dictionary),
+            None => Lz77Decoder::new(&mut read, opts),
+        };
         copy(&mut decoder, &mut write).expect("Something went wrong while decoding");
     }
     if matches.is_present("verbose") {
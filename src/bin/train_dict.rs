@@ -0,0 +1,68 @@
+extern crate lz77;
+extern crate clap;
+
+use std::fs;
+use std::process;
+use lz77::{dict, MIN_WINDOW_BITS, MAX_WINDOW_BITS};
+use clap::{Arg, App};
+
+fn main() {
+    let matches = App::new("Lz77 dictionary trainer")
+        .version("0.1")
+        .author("Moritz Wanzenböck <moritz.wanzenboeck@gmail.com>")
+        .about("Builds a preset dictionary from sample files")
+        .arg(Arg::with_name("OUTPUT")
+            .required(true)
+            .help("Sets the dictionary file to write")
+            .index(1))
+        .arg(Arg::with_name("SAMPLES")
+            .required(true)
+            .multiple(true)
+            .help("Sets the sample files to train on")
+            .index(2))
+        .arg(Arg::with_name("window_bits")
+            .default_value("12")
+            .short("w")
+            .long("window")
+            .help("Sets the window size in bits of the streams the dictionary is used for"))
+        .arg(Arg::with_name("max_size")
+            .takes_value(true)
+            .short("m")
+            .long("max-size")
+            .help("Sets the maximum dictionary size in bytes, by default the window size"))
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .help("Sets verbose output"))
+        .get_matches();
+
+    let window_bits: u8 = matches.value_of("window_bits")
+        .and_then(|size| size.parse::<u8>().ok())
+        .expect("Invalid value for window_bits");
+    if !(MIN_WINDOW_BITS..=MAX_WINDOW_BITS).contains(&window_bits) {
+        eprintln!("Window size must be between {} and {} bits", MIN_WINDOW_BITS, MAX_WINDOW_BITS);
+        process::exit(1);
+    }
+    // A larger dictionary does not fit into the window.
+    let window_capacity = (1 << window_bits as usize) - 1;
+    let max_size = matches.value_of("max_size")
+        .map_or(window_capacity, |size| {
+            size.parse::<usize>().expect("Invalid value for max_size")
+        });
+
+    let samples: Vec<Vec<u8>> = matches.values_of("SAMPLES")
+        .expect("No samples given")
+        .map(|file| fs::read(file).expect("Sample: No such file"))
+        .collect();
+    let sample_slices: Vec<&[u8]> = samples.iter().map(|sample| &sample[..]).collect();
+    let dictionary = dict::train(&sample_slices, max_size);
+    fs::write(matches.value_of("OUTPUT").expect("No output given"), &dictionary)
+        .expect("Output: Could not write file");
+
+    if matches.is_present("verbose") {
+        let read: usize = samples.iter().map(|sample| sample.len()).sum();
+        println!("Read:    {} bytes from {} samples", read, samples.len());
+        println!("Written: {} bytes, dictionary ID {:#010x}",
+                 dictionary.len(),
+                 dict::id(&dictionary));
+    }
+}
@@ -1,6 +1,5 @@
 use std::ops::{Index, IndexMut};
 use std::fmt;
-use std::io;
 use std::cmp;
 
 pub trait SizedBuffer {
@@ -13,6 +12,12 @@ impl<T> SizedBuffer for Vec<T> {
     }
 }
 
+impl<T> SizedBuffer for [T] {
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
 pub struct RingBuffer<T> {
     cur_start: usize,
     buf: Vec<T>,
@@ -63,6 +68,12 @@ impl<T> RingBuffer<T> {
         }
     }
 
+    /// Empties the buffer, keeping its capacity.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.cur_start = 0;
+    }
+
     pub fn push(&mut self, item: T) {
         let mut i = self.buf.len();
         if i == self.buf.capacity() {
@@ -76,37 +87,33 @@ impl<T> RingBuffer<T> {
 }
 
 impl RingBuffer<u8> {
-    pub fn read_to_buf<R>(&mut self, reader: &mut R, size: usize) -> io::Result<()>
-        where R: io::Read
-    {
+    pub fn push_slice(&mut self, bytes: &[u8]) {
         let mut pos = 0;
 
-        while pos < size {
+        while pos < bytes.len() {
             if self.buf.len() == self.buf.capacity() {
-                let to_copy = cmp::min(self.buf.capacity() - self.cur_start, size - pos);
-                reader.read_exact(&mut self.buf[self.cur_start..(self.cur_start + to_copy)])?;
+                let to_copy = cmp::min(self.buf.capacity() - self.cur_start, bytes.len() - pos);
+                self.buf[self.cur_start..(self.cur_start + to_copy)]
+                    .copy_from_slice(&bytes[pos..(pos + to_copy)]);
                 self.cur_start = (self.cur_start + to_copy) % self.buf.capacity();
                 pos += to_copy;
             } else {
-                let to_copy = cmp::min(self.buf.capacity() - self.buf.len(), size - pos);
-                let buf_end = self.buf.len();
-                unsafe { self.buf.set_len(buf_end + to_copy) };
-                reader.read_exact(&mut self.buf[buf_end..(buf_end + to_copy)])?;
+                let to_copy = cmp::min(self.buf.capacity() - self.buf.len(), bytes.len() - pos);
+                self.buf.extend_from_slice(&bytes[pos..(pos + to_copy)]);
                 pos += to_copy;
             }
         }
-        Ok(())
     }
 }
 
 pub struct CombinedBuffer<'a, 'b, A, B, T>(pub &'a A, pub &'b B)
-    where A: 'a + SizedBuffer + Index<usize, Output = T>,
-          B: 'b + SizedBuffer + Index<usize, Output = T>;
+    where A: 'a + SizedBuffer + Index<usize, Output = T> + ?Sized,
+          B: 'b + SizedBuffer + Index<usize, Output = T> + ?Sized;
 
 
 impl<'a, 'b, A, B, T> Index<usize> for CombinedBuffer<'a, 'b, A, B, T>
-    where A: 'a + SizedBuffer + Index<usize, Output = T>,
-          B: 'b + SizedBuffer + Index<usize, Output = T>
+    where A: 'a + SizedBuffer + Index<usize, Output = T> + ?Sized,
+          B: 'b + SizedBuffer + Index<usize, Output = T> + ?Sized
 {
     type Output = T;
 
@@ -120,8 +127,8 @@ impl<'a, 'b, A, B, T> Index<usize> for CombinedBuffer<'a, 'b, A, B, T>
 }
 
 impl<'a, 'b, A, B, T> SizedBuffer for CombinedBuffer<'a, 'b, A, B, T>
-    where A: 'a + SizedBuffer + Index<usize, Output = T>,
-          B: 'b + SizedBuffer + Index<usize, Output = T>
+    where A: 'a + SizedBuffer + Index<usize, Output = T> + ?Sized,
+          B: 'b + SizedBuffer + Index<usize, Output = T> + ?Sized
 {
     fn len(&self) -> usize {
         self.0.len() + self.1.len()
@@ -129,8 +136,8 @@ impl<'a, 'b, A, B, T> SizedBuffer for CombinedBuffer<'a, 'b, A, B, T>
 }
 
 impl<'a, 'b, A, B, T> fmt::Debug for CombinedBuffer<'a, 'b, A, B, T>
-    where A: 'a + SizedBuffer + Index<usize, Output = T> + fmt::Debug,
-          B: 'b + SizedBuffer + Index<usize, Output = T> + fmt::Debug
+    where A: 'a + SizedBuffer + Index<usize, Output = T> + fmt::Debug + ?Sized,
+          B: 'b + SizedBuffer + Index<usize, Output = T> + fmt::Debug + ?Sized
 {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(fmt)?;
@@ -142,7 +149,6 @@ impl<'a, 'b, A, B, T> fmt::Debug for CombinedBuffer<'a, 'b, A, B, T>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
 
     #[test]
     fn test_ring_buffer() {
@@ -174,18 +180,16 @@ mod tests {
     }
 
     #[test]
-    fn test_ring_buffer_read() {
+    fn test_ring_buffer_push_slice() {
         let mut ring = RingBuffer::new(4);
         let vec = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-        let mut cursor = Cursor::new(&vec);
-        assert!(ring.read_to_buf(&mut cursor, 10).is_ok());
+        ring.push_slice(&vec);
         assert_eq!(ring[0], 7);
         assert_eq!(ring[1], 8);
         assert_eq!(ring[2], 9);
         assert_eq!(ring[3], 10);
 
-        let mut cursor2 = Cursor::new(&vec[..4]);
-        assert!(ring.read_to_buf(&mut cursor2, 4).is_ok());
+        ring.push_slice(&vec[..4]);
         assert_eq!(ring[0], 1);
         assert_eq!(ring[1], 2);
         assert_eq!(ring[2], 3);
@@ -0,0 +1,73 @@
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u32; 256] = make_table();
+
+/// Running CRC-32 (IEEE) over the uncompressed content of a stream.
+#[derive(Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { state: 0xffff_ffff }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        let mut crc = self.state;
+        for &b in buf {
+            crc = TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.state = crc;
+    }
+
+    pub fn sum(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Crc32;
+
+    #[test]
+    fn test_crc32() {
+        let crc = Crc32::new();
+        assert_eq!(crc.sum(), 0);
+
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.sum(), 0xcbf4_3926);
+
+        let mut split = Crc32::new();
+        split.update(b"1234");
+        split.update(b"56789");
+        assert_eq!(split.sum(), crc.sum());
+    }
+}
@@ -1,6 +1,7 @@
+use std::cmp;
 use std::mem;
 use std::fmt;
-use std::u16;
+use std::io;
 use errors::*;
 
 extern crate byteorder;
@@ -15,8 +16,8 @@ pub struct CodeWord {
 impl CodeWord {
     pub fn new(length_field_size: u8) -> Result<Self> {
         let max_len = mem::size_of::<u16>() * 8;
-        if length_field_size as usize <= 0 && length_field_size as usize >= max_len {
-            Err("CodeWord needs length_field has invalid size".into())
+        if length_field_size == 0 || length_field_size as usize >= max_len {
+            Err(Error::InvalidOptions("codeword length field must be between 1 and 15 bits"))
         } else {
             Ok(CodeWord {
                 inner: 0,
@@ -47,7 +48,10 @@ impl CodeWord {
             self.inner = (dist << self.len_field_size) ^ l;
             Ok(())
         } else {
-            Err("Distance field to long for CodeWord".into())
+            Err(Error::CodeWordOverflow {
+                field: "distance",
+                value: dist as usize,
+            })
         }
     }
 
@@ -57,7 +61,10 @@ impl CodeWord {
             self.inner = (self.inner & !mask) ^ length;
             Ok(())
         } else {
-            Err("Length field to long for CodeWord".into())
+            Err(Error::CodeWordOverflow {
+                field: "length",
+                value: length as usize,
+            })
         }
     }
 
@@ -66,7 +73,7 @@ impl CodeWord {
     }
 
     pub fn read(&mut self, buf: &[u8]) {
-        self.inner = BigEndian::read_u16(&buf[..]);
+        self.inner = BigEndian::read_u16(buf);
     }
 
     pub fn as_bytes(&self) -> [u8; 2] {
@@ -82,6 +89,157 @@ impl fmt::Debug for CodeWord {
     }
 }
 
+/// Longest LEB128 encoding of a `u32`.
+pub const MAX_VARINT_SIZE: usize = 5;
+
+/// Writes `value` as LEB128 into `buf` and returns the number of bytes used.
+pub fn write_varint(mut value: u32, buf: &mut [u8; MAX_VARINT_SIZE]) -> usize {
+    let mut size = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[size] = byte;
+            return size + 1;
+        }
+        buf[size] = byte | 0x80;
+        size += 1;
+    }
+}
+
+/// Returns the number of bytes `write_varint` uses for `value`.
+pub fn varint_size(value: usize) -> usize {
+    let bits = 64 - (value as u64).leading_zeros() as usize;
+    cmp::max(bits.div_ceil(7), 1)
+}
+
+/// Decodes a LEB128 value one byte at a time.
+#[derive(Default)]
+pub struct VarintReader {
+    value: u32,
+    shift: u32,
+}
+
+impl VarintReader {
+    /// Adds the next byte and returns the value once it is complete.
+    pub fn push(&mut self, byte: u8) -> Result<Option<u32>> {
+        // The fifth byte only has room for the top four bits of a `u32`.
+        if self.shift == 28 && byte & 0xf0 != 0 {
+            return Err(Error::InvalidVarint);
+        }
+        self.value |= ((byte & 0x7f) as u32) << self.shift;
+        if byte & 0x80 != 0 {
+            self.shift += 7;
+            return Ok(None);
+        }
+        let value = self.value;
+        *self = VarintReader::default();
+        Ok(Some(value))
+    }
+}
+
+/// Serialization of the distance and length of a token.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CodeWordFormat {
+    /// A 16-bit `CodeWord` with the distance in the upper `window_bits` bits.
+    Fixed { window_bits: u8 },
+    /// The distance followed by the length, each as a LEB128 varint.
+    Varint { window_bits: u8 },
+}
+
+impl CodeWordFormat {
+    pub fn new(window_bits: u8, varint: bool) -> Self {
+        if varint {
+            CodeWordFormat::Varint { window_bits }
+        } else {
+            CodeWordFormat::Fixed { window_bits }
+        }
+    }
+
+    pub fn window_bits(&self) -> u8 {
+        match *self {
+            CodeWordFormat::Fixed { window_bits } |
+            CodeWordFormat::Varint { window_bits } => window_bits,
+        }
+    }
+
+    /// Returns the largest length a decoder accepts. A literal run of this length plus one, or
+    /// a match of this length and the literal following it, still fit into the window.
+    pub fn max_len(&self) -> usize {
+        match *self {
+            CodeWordFormat::Fixed { window_bits } => (1 << (16 - window_bits)) - 1,
+            CodeWordFormat::Varint { window_bits } => (1 << window_bits) - 2,
+        }
+    }
+
+    /// Returns the size of the codeword for `distance` and `length`.
+    pub fn size(&self, distance: usize, length: usize) -> usize {
+        match *self {
+            CodeWordFormat::Fixed { .. } => 2,
+            CodeWordFormat::Varint { .. } => varint_size(distance) + varint_size(length),
+        }
+    }
+
+    /// Returns the largest size of a codeword.
+    pub fn max_size(&self) -> usize {
+        match *self {
+            CodeWordFormat::Fixed { .. } => 2,
+            CodeWordFormat::Varint { window_bits } => {
+                let mut buf = [0u8; MAX_VARINT_SIZE];
+                write_varint(1 << window_bits, &mut buf) + write_varint(self.max_len() as u32, &mut buf)
+            }
+        }
+    }
+
+    /// Parses the codeword at the start of `buf` into its distance, length and size, or returns
+    /// `None` if `buf` ends before the codeword does.
+    pub fn parse(&self, buf: &[u8]) -> Result<Option<(usize, usize, usize)>> {
+        match *self {
+            CodeWordFormat::Fixed { window_bits } => {
+                if buf.len() < 2 {
+                    return Ok(None);
+                }
+                let mut cw = CodeWord::new(16 - window_bits)?;
+                cw.read(&buf[..2]);
+                Ok(Some((cw.get_distance() as usize, cw.get_length() as usize, 2)))
+            }
+            CodeWordFormat::Varint { .. } => {
+                let mut reader = VarintReader::default();
+                let mut distance = None;
+                for (i, &byte) in buf.iter().enumerate() {
+                    if let Some(value) = reader.push(byte)? {
+                        match distance {
+                            None => distance = Some(value as usize),
+                            Some(distance) => return Ok(Some((distance, value as usize, i + 1))),
+                        }
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn write<W>(&self, out: &mut W, distance: usize, length: usize) -> io::Result<()>
+        where W: io::Write
+    {
+        match *self {
+            CodeWordFormat::Fixed { window_bits } => {
+                let code = CodeWord::new_with_data(16 - window_bits, distance as u16, length as u16)?;
+                out.write_all(&code.as_bytes()[..])
+            }
+            CodeWordFormat::Varint { .. } => {
+                let mut buf = [0u8; 2 * MAX_VARINT_SIZE];
+                let mut varint = [0u8; MAX_VARINT_SIZE];
+                let n = write_varint(distance as u32, &mut varint);
+                buf[..n].copy_from_slice(&varint[..n]);
+                let m = write_varint(length as u32, &mut varint);
+                buf[n..(n + m)].copy_from_slice(&varint[..m]);
+                out.write_all(&buf[..(n + m)])
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,10 +257,21 @@ mod tests {
         assert!(cw.set_distance(255).is_ok());
         assert_eq!(cw.get_length(), 1);
         assert_eq!(cw.get_distance(), 255);
-        assert!(cw.set_length(256).is_err());
+        assert_eq!(cw.set_length(256),
+                   Err(Error::CodeWordOverflow {
+                       field: "length",
+                       value: 256,
+                   }));
         assert!(cw.set_distance(256).is_err());
     }
 
+    #[test]
+    fn test_codeword_invalid_size() {
+        assert!(CodeWord::new(0).is_err());
+        assert!(CodeWord::new(16).is_err());
+        assert!(CodeWord::new(15).is_ok());
+    }
+
     #[test]
     fn test_conversion() {
         let cw = CodeWord::new_with_data(4, 2048, 15).expect("CodeWord not valid");
@@ -113,4 +282,42 @@ mod tests {
         assert_eq!(cw.get_length(), cw_copy.get_length());
         assert_eq!(cw.get_distance(), cw.get_distance());
     }
+
+    #[test]
+    fn test_varint() {
+        for &value in &[0u32, 1, 127, 128, 300, 16383, 16384, 1 << 24, u32::MAX] {
+            let mut buf = [0u8; MAX_VARINT_SIZE];
+            let size = write_varint(value, &mut buf);
+            assert_eq!(varint_size(value as usize), size);
+            let mut reader = VarintReader::default();
+            for &byte in &buf[..(size - 1)] {
+                assert_eq!(reader.push(byte), Ok(None));
+            }
+            assert_eq!(reader.push(buf[size - 1]), Ok(Some(value)));
+        }
+        let mut reader = VarintReader::default();
+        for _ in 0..4 {
+            assert_eq!(reader.push(0xff), Ok(None));
+        }
+        assert_eq!(reader.push(0x10), Err(Error::InvalidVarint));
+    }
+
+    #[test]
+    fn test_codeword_format() {
+        let fixed = CodeWordFormat::new(12, false);
+        assert_eq!(fixed.max_len(), 15);
+        let mut out = Vec::new();
+        fixed.write(&mut out, 2048, 15).unwrap();
+        assert_eq!(out, [0x80, 0x0f]);
+
+        let varint = CodeWordFormat::new(20, true);
+        assert_eq!(varint.max_len(), (1 << 20) - 2);
+        assert_eq!(varint.max_size(), 6);
+        let mut out = Vec::new();
+        varint.write(&mut out, 300, 5).unwrap();
+        assert_eq!(out, [0xac, 0x02, 0x05]);
+        assert_eq!(varint.parse(&out[..2]), Ok(None));
+        assert_eq!(varint.parse(&out), Ok(Some((300, 5, 3))));
+        assert_eq!(fixed.parse(&[0x80, 0x0f, 0x00]), Ok(Some((2048, 15, 2))));
+    }
 }
@@ -0,0 +1,573 @@
+use std::io;
+use std::cmp;
+use ::Lz77Options;
+use searcher::{Searcher, SearchResult};
+use buffer::{RingBuffer, CombinedBuffer, SizedBuffer};
+use parser::{self, ParseState, PendingMatch};
+use optimal::Block;
+use token::{Token, TokenWriter};
+use header::{Header, HEADER_SIZE, FLAG_DICTIONARY, DICTIONARY_ID_SIZE};
+use frame::{BlockHeader, BlockIndex, END_OF_BLOCKS};
+use checksum::Crc32;
+use decompressor::Status;
+use errors::Error;
+use dict;
+
+/// What `Compressor::compress` does once it consumed all of its input.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FlushMode {
+    /// Keeps the bytes still needed to find matches, producing output only as tokens are
+    /// chosen.
+    None,
+    /// Encodes everything consumed so far, so that it can be decoded from the output.
+    Sync,
+    /// Encodes everything and ends the stream. No more input is accepted afterwards.
+    Finish,
+}
+
+/// Encoder state machine that compresses into caller-provided buffers, without doing any I/O
+/// itself.
+///
+/// Compressed bytes that do not fit into the output are held back and handed out by the next
+/// calls, which consume no input until they are all written. `Lz77Encoder` wraps it for
+/// writers.
+pub struct Compressor<S>
+    where S: Searcher
+{
+    searcher: S,
+    window: RingBuffer<u8>,
+    /// Stream offset of the end of the window, the number of bytes encoded so far.
+    window_end: usize,
+    /// Bytes not encoded yet start at `lookahead_start`. The ones before it were already moved
+    /// into the window and are dropped when the buffer is refilled.
+    unmatched_data: Vec<u8>,
+    lookahead_start: usize,
+    forward_search_size: usize,
+    tokens: TokenWriter,
+    options: Lz77Options,
+    header_written: bool,
+    finished: bool,
+    checksum: Crc32,
+    pending: Option<PendingMatch>,
+    block: Option<Block>,
+    /// Compressed bytes not handed out yet, from `staged_start` on.
+    staged: Vec<u8>,
+    staged_start: usize,
+    /// Size of the blocks the content is split into, if it is.
+    block_size: Option<usize>,
+    /// Number of bytes consumed into the current block, its checksum and its compressed bytes
+    /// collected so far, which are staged once the block header is known.
+    block_len: usize,
+    block_checksum: Crc32,
+    block_data: Vec<u8>,
+    /// Index of the blocks written so far and the stream offset of the next block, if the
+    /// stream ends with an index.
+    index: Option<BlockIndex>,
+    block_offset: u64,
+    /// Part of the preset dictionary that fits into the window, which starts out holding it,
+    /// and the ID of the whole dictionary.
+    dictionary: Vec<u8>,
+    dictionary_id: Option<u32>,
+}
+
+impl<S> Compressor<S>
+    where S: Searcher + Default
+{
+    /// Creates a compressor with a default searcher tuned by `options`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
+    pub fn new(options: Lz77Options) -> Self {
+        let mut searcher = S::default();
+        searcher.configure(&options);
+        Self::with_searcher(options, searcher)
+    }
+
+    /// Creates a compressor with a default searcher whose window starts out holding
+    /// `dictionary`. The dictionary ID is written into the stream header, unless `options.raw`
+    /// is set, and a decoder needs the same dictionary.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
+    pub fn with_dictionary(options: Lz77Options, dictionary: &[u8]) -> Self {
+        let mut compressor = Self::new(options);
+        let capacity = (1 << compressor.options.window_bits as usize) - 1;
+        compressor.dictionary = dictionary[dictionary.len().saturating_sub(capacity)..].to_vec();
+        compressor.dictionary_id = Some(dict::id(dictionary));
+        compressor.block_offset += DICTIONARY_ID_SIZE as u64;
+        compressor.load_dictionary();
+        compressor
+    }
+}
+
+impl<S> Compressor<S>
+    where S: Searcher
+{
+    /// Creates a compressor using the given searcher instance. The searcher is reset, but keeps
+    /// its tuning parameters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
+    pub fn with_searcher(options: Lz77Options, mut searcher: S) -> Self {
+        if let Err(e) = options.validate() {
+            panic!("{}", e);
+        }
+        let size: usize = (1 << options.window_bits as usize) - 1;
+        let forward_search_size = options.match_len_limit() + 1;
+        searcher.reset();
+        let block_size = options.frame_block_size();
+        Compressor {
+            window: RingBuffer::new(size),
+            window_end: 0,
+            unmatched_data: Vec::with_capacity(2 * (forward_search_size + 1)),
+            lookahead_start: 0,
+            forward_search_size,
+            searcher,
+            tokens: TokenWriter::new(&options),
+            header_written: false,
+            finished: false,
+            checksum: Crc32::new(),
+            pending: None,
+            block: None,
+            staged: Vec::new(),
+            staged_start: 0,
+            block_size,
+            block_len: 0,
+            block_checksum: Crc32::new(),
+            block_data: Vec::new(),
+            index: if block_size.is_some() && options.block_index {
+                Some(BlockIndex::default())
+            } else {
+                None
+            },
+            block_offset: HEADER_SIZE as u64,
+            dictionary: Vec::new(),
+            dictionary_id: None,
+            options,
+        }
+    }
+
+    /// Compresses as much of `input` into `output` as possible, then flushes as requested by
+    /// `flush`. Returns the number of bytes consumed from `input`, the number of bytes written
+    /// to `output` and why it stopped.
+    ///
+    /// Once it reports `OutputFull`, the same flush mode has to be passed again, together with
+    /// the input not consumed yet, until it reports `NeedsInput`, or `Done` when finishing.
+    /// Input passed after finishing is not consumed and reported as `Error::StreamFinished`.
+    pub fn compress(&mut self,
+                    input: &[u8],
+                    output: &mut [u8],
+                    flush: FlushMode)
+                    -> (usize, usize, Status) {
+        if !self.finished {
+            self.write_header();
+        }
+        let mut produced = self.drain(output);
+        if self.finished {
+            let status = if !input.is_empty() {
+                Status::Failed(Error::StreamFinished)
+            } else if self.staged.is_empty() {
+                Status::Done
+            } else {
+                Status::OutputFull
+            };
+            return (0, produced, status);
+        }
+
+        let mut consumed = 0;
+        while consumed < input.len() && self.staged.is_empty() {
+            let mut buf = &input[consumed..];
+            if let Some(size) = self.block_size {
+                // A block only holds the tokens for its own bytes.
+                buf = &buf[..cmp::min(buf.len(), size - self.block_len)];
+            }
+            let (n, full) = match self.fill_forward_buffer(buf) {
+                Some(n) => (n, true),
+                None => (buf.len(), false),
+            };
+            self.checksum.update(&buf[..n]);
+            self.block_checksum.update(&buf[..n]);
+            self.block_len += n;
+            consumed += n;
+            if full {
+                let key_len = self.forward_search_size - 1;
+                self.emitting(|c| parser::step(c, key_len));
+            }
+            if Some(self.block_len) == self.block_size {
+                self.emitting(Self::end_block);
+            }
+            produced += self.drain(&mut output[produced..]);
+        }
+        if consumed < input.len() {
+            return (consumed, produced, Status::OutputFull);
+        }
+
+        match flush {
+            FlushMode::None => {}
+            FlushMode::Sync => self.emitting(Self::end_block),
+            FlushMode::Finish => {
+                self.emitting(Self::end_block);
+                self.emitting(Self::write_trailer);
+                self.finished = true;
+            }
+        }
+        produced += self.drain(&mut output[produced..]);
+        let status = if !self.staged.is_empty() {
+            Status::OutputFull
+        } else if self.finished {
+            Status::Done
+        } else {
+            Status::NeedsInput
+        };
+        (consumed, produced, status)
+    }
+
+    /// Returns whether compressed bytes are held back, waiting for more output.
+    pub fn has_pending_output(&self) -> bool {
+        !self.staged.is_empty()
+    }
+
+    /// Runs `f`, which only writes tokens to `staged`. Writing to a vector never fails.
+    fn emitting<F>(&mut self, f: F)
+        where F: FnOnce(&mut Self) -> io::Result<()>
+    {
+        f(self).expect("Writing to memory failed");
+    }
+
+    /// Copies staged bytes to `output` and returns their number.
+    fn drain(&mut self, output: &mut [u8]) -> usize {
+        let n = cmp::min(self.staged.len() - self.staged_start, output.len());
+        output[..n].copy_from_slice(&self.staged[self.staged_start..(self.staged_start + n)]);
+        self.staged_start += n;
+        if self.staged_start == self.staged.len() {
+            self.staged.clear();
+            self.staged_start = 0;
+        }
+        n
+    }
+
+    fn write_header(&mut self) {
+        if !self.options.raw && !self.header_written {
+            let mut header = Header::from_options(&self.options);
+            if self.dictionary_id.is_some() {
+                header.flags |= FLAG_DICTIONARY;
+            }
+            self.staged.extend_from_slice(&header.as_bytes()[..]);
+            if let Some(id) = self.dictionary_id {
+                self.staged.extend_from_slice(&id.to_be_bytes());
+            }
+            self.header_written = true;
+        }
+    }
+
+    /// Encodes the lookahead and everything held back for choosing tokens.
+    fn flush_tokens(&mut self) -> io::Result<()> {
+        while !self.lookahead().is_empty() {
+            let key_len = self.lookahead().len() - 1;
+            parser::step(self, key_len)?;
+        }
+        parser::finish(self)?;
+        let out = if self.block_size.is_some() {
+            &mut self.block_data
+        } else {
+            &mut self.staged
+        };
+        self.tokens.flush(out)
+    }
+
+    /// Encodes everything consumed so far. When splitting the content into blocks, this also
+    /// stages the current block behind its header and, unless blocks are linked, empties the
+    /// window for the next one.
+    fn end_block(&mut self) -> io::Result<()> {
+        self.flush_tokens()?;
+        if self.block_size.is_none() || self.block_len == 0 {
+            return Ok(());
+        }
+        let header = BlockHeader {
+            compressed_size: self.block_data.len(),
+            uncompressed_size: self.block_len,
+            checksum: if self.options.block_checksum {
+                Some(self.block_checksum.sum())
+            } else {
+                None
+            },
+        };
+        if let Some(ref mut index) = self.index {
+            index.push(self.block_offset, self.block_len);
+        }
+        let staged_len = self.staged.len();
+        header.write(&mut self.staged);
+        self.staged.extend_from_slice(&self.block_data);
+        self.block_offset += (self.staged.len() - staged_len) as u64;
+        self.block_data.clear();
+        self.block_len = 0;
+        self.block_checksum = Crc32::new();
+        if !self.options.linked_blocks {
+            self.window.clear();
+            self.searcher.evict(self.window_end);
+            self.load_dictionary();
+        }
+        Ok(())
+    }
+
+    /// Writes the end marker, or the end of the blocks, and the trailer, which terminate a
+    /// stream that carries a header. Raw streams simply end after the last codeword.
+    fn write_trailer(&mut self) -> io::Result<()> {
+        if self.options.raw {
+            return Ok(());
+        }
+        if self.block_size.is_some() {
+            self.staged.extend_from_slice(&END_OF_BLOCKS);
+            if let Some(ref index) = self.index {
+                index.write(&mut self.staged);
+            }
+        } else {
+            self.tokens.write_end_marker(&mut self.staged)?;
+        }
+        if self.options.checksum {
+            let sum = self.checksum.sum();
+            self.staged.extend_from_slice(&sum.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    /// Moves the dictionary into the empty window, as if it was encoded right before.
+    fn load_dictionary(&mut self) {
+        self.window.push_slice(&self.dictionary);
+        self.searcher.insert(self.window_end, &self.dictionary);
+        self.window_end += self.dictionary.len();
+    }
+
+    fn lookahead(&self) -> &[u8] {
+        &self.unmatched_data[self.lookahead_start..]
+    }
+
+    fn fill_forward_buffer(&mut self, buf: &[u8]) -> Option<usize> {
+        // Dropping the encoded bytes only once they outnumber the lookahead keeps the copying
+        // linear in the input size.
+        if self.lookahead_start > self.forward_search_size {
+            self.unmatched_data.drain(..self.lookahead_start);
+            self.lookahead_start = 0;
+        }
+        let missing = self.forward_search_size + 1 - self.lookahead().len();
+        let extra_cap = cmp::min(missing, buf.len());
+        self.unmatched_data.extend_from_slice(&buf[..extra_cap]);
+        if extra_cap == missing {
+            Some(extra_cap)
+        } else {
+            None
+        }
+    }
+
+    fn move_unmatched_to_window(&mut self, n: usize) {
+        let start = self.lookahead_start;
+        let window_start = self.window_end - self.window.len();
+        let bytes = &self.unmatched_data[start..(start + n)];
+        for &item in bytes {
+            self.window.push(item);
+        }
+        self.searcher.insert(self.window_end, bytes);
+        self.window_end += n;
+        self.lookahead_start += n;
+        if self.window_end - self.window.len() != window_start {
+            self.searcher.evict(self.window_end - self.window.len());
+        }
+    }
+}
+
+impl<S> ParseState for Compressor<S>
+    where S: Searcher
+{
+    fn options(&self) -> &Lz77Options {
+        &self.options
+    }
+
+    fn pending(&mut self) -> &mut Option<PendingMatch> {
+        &mut self.pending
+    }
+
+    fn search(&mut self, key_len: usize) -> Option<SearchResult> {
+        let lookahead = &self.unmatched_data[self.lookahead_start..];
+        let search_buf = CombinedBuffer(&self.window, lookahead);
+        self.searcher.find_longest_match(&search_buf, &lookahead[..key_len])
+    }
+
+    fn search_all(&mut self, key_len: usize, matches: &mut Vec<SearchResult>) {
+        let lookahead = &self.unmatched_data[self.lookahead_start..];
+        let search_buf = CombinedBuffer(&self.window, lookahead);
+        self.searcher.find_matches(&search_buf, &lookahead[..key_len], matches)
+    }
+
+    fn block(&mut self) -> &mut Option<Block> {
+        &mut self.block
+    }
+
+    fn window_len(&self) -> usize {
+        self.window.len()
+    }
+
+    fn window_byte(&self, idx: usize) -> u8 {
+        self.window[idx]
+    }
+
+    fn lookahead_byte(&self, idx: usize) -> u8 {
+        self.unmatched_data[self.lookahead_start + idx]
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.move_unmatched_to_window(n);
+    }
+
+    fn emit(&mut self, token: Token, next: u8) -> io::Result<()> {
+        let out = if self.block_size.is_some() {
+            &mut self.block_data
+        } else {
+            &mut self.staged
+        };
+        self.tokens.write_token(out, token, next)
+    }
+
+    fn literal_run_open(&self) -> bool {
+        self.tokens.has_literals()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compressor, FlushMode};
+    use decompressor::{Decompressor, Status};
+    use header::HEADER_SIZE;
+    use searcher::AnySearcher;
+    use errors::Error;
+    use test_util::sample_data;
+    use ::{compress, Lz77Options};
+
+    /// Compresses `data` in chunks of `chunk` bytes into outputs of `out` bytes.
+    fn compress_chunked(data: &[u8], opts: &Lz77Options, chunk: usize, out: usize) -> Vec<u8> {
+        let mut compressor = Compressor::<AnySearcher>::new(opts.clone());
+        let mut compressed = Vec::new();
+        let mut buf = vec![0u8; out];
+        for input in data.chunks(chunk) {
+            let mut input = input;
+            loop {
+                let (consumed, produced, status) =
+                    compressor.compress(input, &mut buf, FlushMode::None);
+                input = &input[consumed..];
+                compressed.extend_from_slice(&buf[..produced]);
+                if status == Status::NeedsInput {
+                    assert!(input.is_empty());
+                    break;
+                }
+                assert_eq!(status, Status::OutputFull);
+            }
+        }
+        loop {
+            let (consumed, produced, status) =
+                compressor.compress(&[], &mut buf, FlushMode::Finish);
+            assert_eq!(consumed, 0);
+            compressed.extend_from_slice(&buf[..produced]);
+            if status == Status::Done {
+                return compressed;
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_in_chunks() {
+        let data = sample_data(30000);
+        let options = [Lz77Options::default(),
+                       Lz77Options { huffman: true, ..Default::default() },
+                       Lz77Options { optimal: true, ..Default::default() },
+                       Lz77Options { window_bits: 18, raw: true, ..Default::default() },
+                       Lz77Options { block_size: Some(3000), ..Default::default() },
+                       Lz77Options {
+                           block_size: Some(1000),
+                           block_checksum: true,
+                           linked_blocks: true,
+                           huffman: true,
+                           ..Default::default()
+                       },
+                       Lz77Options {
+                           block_size: Some(2000),
+                           block_index: true,
+                           ..Default::default()
+                       }];
+        for opts in &options {
+            let expected = compress(&data, opts);
+            for &(chunk, out) in &[(1, 1), (7, 3), (1000, 1), (4096, 100), (1 << 20, 1 << 20)] {
+                assert_eq!(compress_chunked(&data, opts, chunk, out), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_full() {
+        let data = sample_data(1000);
+        let mut compressor = Compressor::<AnySearcher>::new(Lz77Options::default());
+        let mut buf = [0u8; HEADER_SIZE - 1];
+        // The header alone fills the output.
+        assert_eq!(compressor.compress(&data, &mut buf, FlushMode::None),
+                   (0, HEADER_SIZE - 1, Status::OutputFull));
+        assert!(compressor.has_pending_output());
+        let (consumed, produced, _) = compressor.compress(&data, &mut buf[..1], FlushMode::None);
+        assert_eq!(produced, 1);
+        assert!(consumed > 0);
+    }
+
+    #[test]
+    fn test_sync_flush() {
+        let data = sample_data(5000);
+        let mut compressor = Compressor::<AnySearcher>::new(Lz77Options::default());
+        let mut decompressor = Decompressor::new(Lz77Options::default());
+        let mut compressed = vec![0u8; 10000];
+        let mut output = vec![0u8; 10000];
+        for part in data.chunks(1234) {
+            let (consumed, produced, status) =
+                compressor.compress(part, &mut compressed, FlushMode::Sync);
+            assert_eq!((consumed, status), (part.len(), Status::NeedsInput));
+            // Everything compressed so far decodes without waiting for more.
+            let (read, decoded, status) = decompressor.decompress(&compressed[..produced],
+                                                                  &mut output);
+            assert_eq!((read, status), (produced, Status::NeedsInput));
+            assert_eq!(&output[..decoded], part);
+        }
+        let (_, produced, status) = compressor.compress(&[], &mut compressed, FlushMode::Finish);
+        assert_eq!(status, Status::Done);
+        let (_, decoded, status) = decompressor.decompress(&compressed[..produced], &mut output);
+        assert_eq!((decoded, status), (0, Status::Done));
+        assert_eq!(compressor.compress(b"more", &mut compressed, FlushMode::None),
+                   (0, 0, Status::Failed(Error::StreamFinished)));
+    }
+
+    #[test]
+    fn test_input_after_finish() {
+        let data = sample_data(1000);
+        let opts = Lz77Options::default();
+        let mut compressor = Compressor::<AnySearcher>::new(opts.clone());
+        let mut buf = [0u8; 16];
+        let mut compressed = Vec::new();
+        let mut input = &data[..];
+        while !input.is_empty() {
+            let (consumed, produced, _) = compressor.compress(input, &mut buf, FlushMode::Finish);
+            input = &input[consumed..];
+            compressed.extend_from_slice(&buf[..produced]);
+        }
+        // Output still pending is handed out, but more input is refused.
+        let (consumed, produced, status) =
+            compressor.compress(b"more", &mut buf, FlushMode::Finish);
+        assert_eq!((consumed, status), (0, Status::Failed(Error::StreamFinished)));
+        assert!(produced > 0);
+        compressed.extend_from_slice(&buf[..produced]);
+        loop {
+            let (_, produced, status) = compressor.compress(&[], &mut buf, FlushMode::Finish);
+            compressed.extend_from_slice(&buf[..produced]);
+            if status == Status::Done {
+                break;
+            }
+        }
+        assert_eq!(compressed, compress(&data, &opts));
+    }
+}
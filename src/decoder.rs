@@ -1,44 +1,74 @@
 use std::io;
-use std::cmp;
 use ::Lz77Options;
-use codeword::CodeWord;
-use buffer::{RingBuffer, SizedBuffer};
+use decompressor::{Decompressor, Status};
 
-enum DecoderState {
-    NoData,
-    Data(usize),
-    Drained,
-}
+/// Size of the chunks read from the inner reader.
+const INPUT_BUFFER_SIZE: usize = 1 << 14;
 
+/// Decodes a stream read from `R`, using a `Decompressor`.
+///
+/// The inner reader is read in chunks, so it may be read past the end of the stream.
 pub struct Lz77Decoder<R>
     where R: io::Read
 {
     inner: R,
-    window: RingBuffer<u8>,
-    state: DecoderState,
-    options: Lz77Options,
+    decompressor: Decompressor,
+    input: Vec<u8>,
+    pos: usize,
+    eof: bool,
 }
 
 impl<R> Lz77Decoder<R>
     where R: io::Read
 {
+    /// Creates a decoder reading from `reader`.
+    ///
+    /// Unless `options.raw` is set, the window size is taken from the stream header and the
+    /// one in `options` is ignored. Invalid raw options are reported by the first `read`.
+    ///
+    /// `options.max_output_size` and `options.max_expansion_ratio` are checked before each
+    /// token is expanded, so no more than the allowed output is ever produced.
     pub fn new(reader: R, options: Lz77Options) -> Self {
-        let size: usize = (1 << options.window_size as usize) - 1;
+        Self::with_decompressor(reader, Decompressor::new(options))
+    }
+
+    /// Creates a decoder whose window starts out holding `dictionary`, see
+    /// `Decompressor::with_dictionary`.
+    pub fn with_dictionary(reader: R, options: Lz77Options, dictionary: &[u8]) -> Self {
+        Self::with_decompressor(reader, Decompressor::with_dictionary(options, dictionary))
+    }
+
+    fn with_decompressor(reader: R, decompressor: Decompressor) -> Self {
         Lz77Decoder {
             inner: reader,
-            window: RingBuffer::new(size),
-            state: DecoderState::NoData,
-            options: options,
+            decompressor,
+            input: Vec::with_capacity(INPUT_BUFFER_SIZE),
+            pos: 0,
+            eof: false,
         }
     }
 
-    fn read_token(&mut self) -> io::Result<CodeWord> {
-        let mut token_buf: [u8; 2] = [0; 2];
-        self.inner.read_exact(&mut token_buf[..])?;
-        let mut cw = CodeWord::new(16 - self.options.window_size)
-            .expect("Misaligned length for codewords");
-        cw.read(&token_buf[..]);
-        Ok(cw)
+    /// Reads the next chunk of input once the previous one is used up.
+    fn fill_input(&mut self) -> io::Result<()> {
+        if self.pos < self.input.len() || self.eof {
+            return Ok(());
+        }
+        self.input.resize(INPUT_BUFFER_SIZE, 0);
+        self.pos = 0;
+        loop {
+            match self.inner.read(&mut self.input[..]) {
+                Ok(n) => {
+                    self.input.truncate(n);
+                    self.eof = n == 0;
+                    return Ok(());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.input.clear();
+                    return Err(e);
+                }
+            }
+        }
     }
 }
 
@@ -46,57 +76,197 @@ impl<R> io::Read for Lz77Decoder<R>
     where R: io::Read
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut pos = 0;
-
-        while pos < buf.len() {
-            match self.state {
-                DecoderState::Drained => {
-                    break;
-                }
-                DecoderState::Data(size) => {
-                    let copy_size = cmp::min(buf.len() - pos, size);
-                    let window_start = self.window.len() - size;
-                    for i in 0..copy_size {
-                        buf[pos + i] = self.window[window_start + i]
-                    }
-                    if size - copy_size == 0 {
-                        self.state = DecoderState::NoData;
-                    } else {
-                        self.state = DecoderState::Data(size - copy_size);
-                    }
-                    pos += copy_size;
-                }
-                DecoderState::NoData => {
-                    let token = match self.read_token() {
-                        Ok(x) => x,
-                        Err(e) => {
-                            match e.kind() {
-                                io::ErrorKind::UnexpectedEof => {
-                                    self.state = DecoderState::Drained;
-                                    break;
-                                }
-                                _ => {
-                                    return Err(e.into());
-                                }
-                            }
-                        }
-                    };
-                    if token.get_distance() == 0 {
-                        self.window.read_to_buf(&mut self.inner, token.get_length() as usize)?;
-                    } else {
-                        for _ in 0..token.get_length() {
-                            let idx = self.window.len() - token.get_distance() as usize;
-                            let c = self.window[idx];
-                            self.window.push(c);
-                        }
-                    }
-                    let mut b: [u8; 1] = [0];
-                    self.inner.read_exact(&mut b[..])?;
-                    self.window.push(b[0]);
-                    self.state = DecoderState::Data(token.get_length() as usize + 1);
+        let mut written = 0;
+        loop {
+            if !self.decompressor.is_done() {
+                self.fill_input()?;
+            }
+            let (consumed, produced, status) =
+                self.decompressor.decompress(&self.input[self.pos..], &mut buf[written..]);
+            self.pos += consumed;
+            written += produced;
+            match status {
+                Status::Done | Status::OutputFull => return Ok(written),
+                // The bytes decoded before the error are handed out first.
+                Status::Failed(_) if written > 0 => return Ok(written),
+                Status::Failed(err) => return Err(err.into()),
+                Status::NeedsInput if self.eof => {
+                    self.decompressor.finish()?;
+                    return Ok(written);
                 }
+                Status::NeedsInput => {}
             }
         }
-        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lz77Decoder;
+    use ::Lz77Options;
+    use codeword::CodeWord;
+    use errors::Error;
+    use std::io::{self, Cursor, Read};
+
+    fn decode(data: &[u8], opts: Lz77Options) -> Result<Vec<u8>, Option<Error>> {
+        let mut decoder = Lz77Decoder::new(Cursor::new(data), opts);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output)
+            .map(|_| output)
+            .map_err(|e| e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()).cloned())
+    }
+
+    fn decode_raw(data: &[u8], window_bits: u8) -> Result<Vec<u8>, Option<Error>> {
+        decode(data, Lz77Options { window_bits, raw: true, ..Default::default() })
+    }
+
+    /// A stream of a single literal followed by `matches` back-references of maximum length,
+    /// each expanding 3 bytes into 128.
+    fn expanding_stream(matches: usize) -> Vec<u8> {
+        let mut data = b"LZ77\x01\x09\x00\x00\x00a".to_vec();
+        let token = CodeWord::new_with_data(7, 1, 127).unwrap().as_bytes();
+        for _ in 0..matches {
+            data.extend_from_slice(&token);
+            data.push(b'a');
+        }
+        data.extend_from_slice(&CodeWord::new_with_data(7, 1, 0).unwrap().as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_distance_beyond_window() {
+        // Literal run "ab" followed by a match reaching 3 bytes back.
+        let data = [0x00, 0x01, b'a', b'b', 0x00, 0x32, b'c'];
+        assert_eq!(decode_raw(&data, 12),
+                   Err(Some(Error::DistanceBeyondWindow {
+                       offset: 2,
+                       distance: 3,
+                       window_len: 2,
+                   })));
+    }
+
+    #[test]
+    fn test_truncated_token() {
+        assert_eq!(decode_raw(&[0x00, 0x03, b'a'], 12), Err(Some(Error::TruncatedToken)));
+        assert_eq!(decode_raw(&[0x00, 0x00, b'a', 0x00], 12),
+                   Err(Some(Error::TruncatedToken)));
+        assert_eq!(decode_raw(&[0x00, 0x00, b'a'], 12), Ok(b"a".to_vec()));
+    }
+
+    #[test]
+    fn test_invalid_raw_options() {
+        assert_eq!(decode_raw(&[0x00, 0x00, b'a'], 25),
+                   Err(Some(Error::InvalidOptions("window_bits must be between 9 and 24 bits"))));
+    }
+
+    #[test]
+    fn test_garbage_does_not_panic() {
+        let mut state: u32 = 1;
+        for _ in 0..200 {
+            let data: Vec<u8> = (0..64)
+                .map(|_| {
+                    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                    (state >> 16) as u8
+                })
+                .collect();
+            let _ = decode_raw(&data, 10);
+            let mut framed = b"LZ77\x01\x0a\x01".to_vec();
+            framed.extend_from_slice(&data);
+            let _ = decode(&framed, Lz77Options::default());
+            // A single entropy-coded block of garbage.
+            let mut blocks = b"LZ77\x01\x0a\x03\x00\x00\x00\x40".to_vec();
+            blocks.extend_from_slice(&data);
+            let _ = decode(&blocks, Lz77Options::default());
+            let mut varint = b"LZ77\x01\x10\x05".to_vec();
+            varint.extend_from_slice(&data);
+            let _ = decode(&varint, Lz77Options::default());
+        }
+    }
+
+    #[test]
+    fn test_varint_tokens() {
+        let opts = Lz77Options {
+            window_bits: 10,
+            raw: true,
+            max_match_len: Some(500),
+            ..Default::default()
+        };
+        // Literal run "abc" followed by a match of 300 bytes at distance 3 and the literal "d".
+        let data = [0x00, 0x02, b'a', b'b', b'c', 0x03, 0xac, 0x02, b'd'];
+        let mut expected = b"abc".repeat(101);
+        expected.push(b'd');
+        assert_eq!(decode(&data, opts.clone()), Ok(expected));
+        assert_eq!(decode(&data[..6], opts.clone()), Err(Some(Error::TruncatedToken)));
+        assert_eq!(decode(&[0x00, 0xff, 0xff, 0xff, 0xff, 0x7f], opts.clone()),
+                   Err(Some(Error::InvalidVarint)));
+        assert_eq!(decode(&[0x00, 0xd0, 0x0f], opts),
+                   Err(Some(Error::MatchTooLong {
+                       offset: 0,
+                       length: 2000,
+                   })));
+    }
+
+    #[test]
+    fn test_max_output_size() {
+        let data = expanding_stream(1000);
+        assert_eq!(decode(&data, Lz77Options::default()).map(|out| out.len()), Ok(128001));
+        let limited = Lz77Options { max_output_size: Some(128001), ..Default::default() };
+        assert_eq!(decode(&data, limited).map(|out| out.len()), Ok(128001));
+
+        let limited = Lz77Options { max_output_size: Some(10000), ..Default::default() };
+        let mut decoder = Lz77Decoder::new(Cursor::new(&data), limited);
+        let mut output = Vec::new();
+        let mut buf = [0u8; 1000];
+        let err = loop {
+            match decoder.read(&mut buf) {
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err.get_ref().and_then(|inner| inner.downcast_ref::<Error>()),
+                   Some(&Error::OutputLimitExceeded { limit: 10000 }));
+        assert!(output.len() <= 10000);
+    }
+
+    /// Hands out a single byte per read, so that every token is split between reads.
+    struct ByteReader<'a>(&'a [u8]);
+
+    impl<'a> Read for ByteReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_short_reads() {
+        let data = expanding_stream(10);
+        let expected = decode(&data, Lz77Options::default());
+        let mut decoder = Lz77Decoder::new(ByteReader(&data), Lz77Options::default());
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).expect("Decoding failed");
+        assert_eq!(Ok(output), expected);
+
+        let mut decoder = Lz77Decoder::new(ByteReader(&data[..data.len() - 1]),
+                                           Lz77Options::default());
+        let err = decoder.read_to_end(&mut Vec::new()).expect_err("Truncated stream accepted");
+        assert_eq!(err.get_ref().and_then(|inner| inner.downcast_ref::<Error>()),
+                   Some(&Error::TruncatedToken));
+    }
+
+    #[test]
+    fn test_max_expansion_ratio() {
+        let data = expanding_stream(1000);
+        let limited = Lz77Options { max_expansion_ratio: Some(50), ..Default::default() };
+        assert_eq!(decode(&data, limited).map(|out| out.len()), Ok(128001));
+        let limited = Lz77Options { max_expansion_ratio: Some(20), ..Default::default() };
+        match decode(&data, limited) {
+            Err(Some(Error::OutputLimitExceeded { .. })) => {}
+            other => panic!("Unexpected result {:?}", other.map(|out| out.len())),
+        }
     }
 }
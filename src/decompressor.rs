@@ -0,0 +1,790 @@
+use std::cmp;
+use std::mem;
+use ::{Lz77Options, MIN_WINDOW_BITS};
+use codeword::{CodeWordFormat, MAX_VARINT_SIZE};
+use header::{Header, HEADER_SIZE, FLAG_CHECKSUM, FLAG_HUFFMAN, FLAG_VARINT, FLAG_BLOCKS,
+             FLAG_BLOCK_CHECKSUM, FLAG_LINKED_BLOCKS,
+             FLAG_BLOCK_INDEX, FLAG_DICTIONARY, DICTIONARY_ID_SIZE};
+use frame::{self, BlockHeader, MAX_BLOCK_HEADER_SIZE, INDEX_ENTRY_SIZE, INDEX_FOOTER_SIZE};
+use huffman::{self, BlockDecoder, Symbol};
+use checksum::Crc32;
+use errors::{Error, Result};
+use buffer::{RingBuffer, SizedBuffer};
+use dict;
+
+/// Progress reported by `Decompressor::decompress` and `Compressor::compress`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Status {
+    /// All of the input was consumed, decoding continues with the next input.
+    NeedsInput,
+    /// The output is full, decoding continues with the next output.
+    OutputFull,
+    /// The stream ended with its end marker and trailer. Input after them is not consumed.
+    Done,
+    /// The stream can not be decoded. Every further call reports the same error.
+    Failed(Error),
+}
+
+enum State {
+    /// Waiting for the stream header, or for the raw options to be checked.
+    Header {
+        buf: [u8; HEADER_SIZE],
+        len: usize,
+    },
+    /// Collecting the dictionary ID following the header.
+    DictionaryId { buf: [u8; DICTIONARY_ID_SIZE], len: usize },
+    /// Collecting the bytes of the next codeword.
+    Token {
+        buf: [u8; 2 * MAX_VARINT_SIZE],
+        len: usize,
+    },
+    /// Copying the bytes of a literal run from the input.
+    Literals(usize),
+    /// Copying the bytes of a match from the window.
+    Match { distance: usize, remaining: usize },
+    /// Collecting the size of the next entropy-coded block.
+    BlockSize { buf: [u8; 4], len: usize },
+    /// Collecting the payload of an entropy-coded block.
+    BlockPayload { payload: Vec<u8>, len: usize },
+    /// Decoding the symbols of `block`.
+    Symbols,
+    /// Collecting the header of the next block of a stream split into blocks.
+    FrameHeader {
+        buf: [u8; MAX_BLOCK_HEADER_SIZE],
+        len: usize,
+    },
+    /// Collecting the number of entries at the start of the block index.
+    IndexStart { buf: [u8; 4], len: usize },
+    /// Skipping the `remaining` bytes of the `count` entries of the block index.
+    IndexEntries { remaining: u64, count: u32 },
+    /// Collecting the content size and number of entries ending the block index.
+    IndexFooter {
+        buf: [u8; INDEX_FOOTER_SIZE],
+        len: usize,
+        count: u32,
+    },
+    /// Collecting the content checksum following the end marker.
+    Trailer { buf: [u8; 4], len: usize },
+    Done,
+    Failed(Error),
+}
+
+impl State {
+    fn token() -> Self {
+        State::Token {
+            buf: [0; 2 * MAX_VARINT_SIZE],
+            len: 0,
+        }
+    }
+
+    fn block_size() -> Self {
+        State::BlockSize {
+            buf: [0; 4],
+            len: 0,
+        }
+    }
+
+    fn frame_header() -> Self {
+        State::FrameHeader {
+            buf: [0; MAX_BLOCK_HEADER_SIZE],
+            len: 0,
+        }
+    }
+}
+
+/// The block of a stream split into blocks that is being decoded.
+struct FrameBlock {
+    /// Offset in the compressed stream where the block ends.
+    input_end: u64,
+    /// Decoded offsets of the start and end of the block.
+    start: u64,
+    end: u64,
+    /// The checksum from the block header and the one of the content decoded so far.
+    checksum: Option<(u32, Crc32)>,
+}
+
+/// Decoder state machine that is handed the compressed stream in chunks of any size, without
+/// doing any I/O itself.
+///
+/// Tokens split between chunks are resumed where they stopped, so compressed data can be
+/// decoded as it arrives, for example from an event loop. `Lz77Decoder` wraps it for readers.
+pub struct Decompressor {
+    window: RingBuffer<u8>,
+    state: State,
+    options: Lz77Options,
+    checksum: Option<Crc32>,
+    decoded: u64,
+    consumed: u64,
+    huffman: bool,
+    format: CodeWordFormat,
+    block: Option<BlockDecoder<Vec<u8>>>,
+    /// Flags of a stream split into blocks.
+    framed: bool,
+    block_checksums: bool,
+    linked_blocks: bool,
+    block_index: bool,
+    frame_block: Option<FrameBlock>,
+    /// Start of the output of the current call not added to the block checksum yet.
+    block_summed: usize,
+    /// Preset dictionary the window starts out holding, and its ID.
+    dictionary: Vec<u8>,
+    dictionary_id: Option<u32>,
+}
+
+/// Copies the start of `input[*pos..]` to `buf[*len..]` and returns whether `buf` is full.
+fn fill(input: &[u8], pos: &mut usize, buf: &mut [u8], len: &mut usize) -> bool {
+    let n = cmp::min(buf.len() - *len, input.len() - *pos);
+    buf[*len..(*len + n)].copy_from_slice(&input[*pos..(*pos + n)]);
+    *len += n;
+    *pos += n;
+    *len == buf.len()
+}
+
+impl Decompressor {
+    /// Creates a decompressor for a stream encoded with `options`.
+    ///
+    /// Unless `options.raw` is set, the window size is taken from the stream header and the
+    /// one in `options` is ignored. Invalid raw options are reported by the first call to
+    /// `decompress`.
+    ///
+    /// `options.max_output_size` and `options.max_expansion_ratio` are checked before each
+    /// token is expanded, so no more than the allowed output is ever produced.
+    pub fn new(options: Lz77Options) -> Self {
+        Decompressor {
+            window: RingBuffer::new(0),
+            state: State::Header {
+                buf: [0; HEADER_SIZE],
+                len: 0,
+            },
+            options,
+            checksum: None,
+            decoded: 0,
+            consumed: 0,
+            huffman: false,
+            format: CodeWordFormat::new(MIN_WINDOW_BITS, false),
+            block: None,
+            framed: false,
+            block_checksums: false,
+            linked_blocks: false,
+            block_index: false,
+            frame_block: None,
+            block_summed: 0,
+            dictionary: Vec::new(),
+            dictionary_id: None,
+        }
+    }
+
+    /// Creates a decompressor whose window starts out holding `dictionary`. The stream has to
+    /// be encoded with the same dictionary, which is checked against the dictionary ID in the
+    /// stream header unless `options.raw` is set.
+    pub fn with_dictionary(options: Lz77Options, dictionary: &[u8]) -> Self {
+        let mut decompressor = Self::new(options);
+        decompressor.dictionary = dictionary.to_vec();
+        decompressor.dictionary_id = Some(dict::id(dictionary));
+        decompressor
+    }
+
+    /// Decodes as much of `input` into `output` as possible. Returns the number of bytes
+    /// consumed from `input`, the number of bytes written to `output` and why it stopped.
+    pub fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> (usize, usize, Status) {
+        let mut consumed = 0;
+        let mut produced = 0;
+        let status = match self.run(input, &mut consumed, output, &mut produced) {
+            Ok(status) => status,
+            Err(err) => {
+                self.state = State::Failed(err.clone());
+                Status::Failed(err)
+            }
+        };
+        self.consumed += consumed as u64;
+        if let Some(ref mut crc) = self.checksum {
+            crc.update(&output[..produced]);
+        }
+        if let Some(FrameBlock { checksum: Some((_, ref mut crc)), .. }) = self.frame_block {
+            crc.update(&output[self.block_summed..produced]);
+        }
+        self.block_summed = 0;
+        (consumed, produced, status)
+    }
+
+    /// Tells the decompressor that the input ended. Raw streams may end between two tokens,
+    /// others have to be `Done`.
+    pub fn finish(&mut self) -> Result<()> {
+        let res = match self.state {
+            State::Done => return Ok(()),
+            State::Failed(ref err) => return Err(err.clone()),
+            State::Header { .. } if self.options.raw => {
+                let varint = self.options.varint();
+                self.init_window(varint)
+            }
+            State::Token { len: 0, .. } if self.options.raw => Ok(()),
+            State::Token { len: 0, .. } => Err(Error::TruncatedStream),
+            State::Token { .. } |
+            State::Literals(_) => Err(Error::TruncatedToken),
+            _ => Err(Error::TruncatedStream),
+        };
+        self.state = match res {
+            Ok(()) => State::Done,
+            Err(ref err) => State::Failed(err.clone()),
+        };
+        res
+    }
+
+    /// Returns whether the stream ended, successfully or not.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done | State::Failed(_))
+    }
+
+    fn init_window(&mut self, varint: bool) -> Result<()> {
+        self.options.validate()?;
+        self.format = CodeWordFormat::new(self.options.window_bits, varint);
+        self.window = RingBuffer::new((1 << self.options.window_bits as usize) - 1);
+        self.load_dictionary();
+        Ok(())
+    }
+
+    /// Moves the end of the dictionary that fits into the empty window into it.
+    fn load_dictionary(&mut self) {
+        let capacity = (1 << self.options.window_bits as usize) - 1;
+        let start = self.dictionary.len().saturating_sub(capacity);
+        self.window.push_slice(&self.dictionary[start..]);
+    }
+
+    fn read_header(&mut self, buf: &[u8; HEADER_SIZE]) -> Result<State> {
+        let header = Header::parse(buf)?;
+        self.options.window_bits = header.window_bits;
+        self.init_window(header.has_flag(FLAG_VARINT))?;
+        if header.has_flag(FLAG_CHECKSUM) {
+            self.checksum = Some(Crc32::new());
+        }
+        self.huffman = header.has_flag(FLAG_HUFFMAN);
+        self.framed = header.has_flag(FLAG_BLOCKS);
+        self.block_checksums = header.has_flag(FLAG_BLOCK_CHECKSUM);
+        self.linked_blocks = header.has_flag(FLAG_LINKED_BLOCKS);
+        self.block_index = header.has_flag(FLAG_BLOCK_INDEX);
+        if header.has_flag(FLAG_DICTIONARY) {
+            return Ok(State::DictionaryId {
+                buf: [0; DICTIONARY_ID_SIZE],
+                len: 0,
+            });
+        }
+        self.check_dictionary(None)
+    }
+
+    /// Checks that the stream needs the dictionary with ID `expected`, and returns the state
+    /// reading its content.
+    fn check_dictionary(&self, expected: Option<u32>) -> Result<State> {
+        if expected != self.dictionary_id {
+            return Err(Error::DictionaryMismatch {
+                expected,
+                actual: self.dictionary_id,
+            });
+        }
+        Ok(if self.framed {
+            State::frame_header()
+        } else {
+            self.first_token()
+        })
+    }
+
+    /// Returns the state reading the first token of the stream or block.
+    fn first_token(&self) -> State {
+        if self.huffman {
+            State::block_size()
+        } else {
+            State::token()
+        }
+    }
+
+    /// Starts decoding the block described by `header`, which ends after `consumed` bytes of
+    /// the stream plus its compressed size.
+    fn start_frame_block(&mut self, header: BlockHeader, consumed: u64) -> State {
+        if !self.linked_blocks {
+            self.window.clear();
+            self.load_dictionary();
+        }
+        self.frame_block = Some(FrameBlock {
+            input_end: consumed + header.compressed_size as u64,
+            start: self.decoded,
+            end: self.decoded + header.uncompressed_size as u64,
+            checksum: header.checksum.map(|sum| (sum, Crc32::new())),
+        });
+        self.first_token()
+    }
+
+    /// Checks the content of the block that just ended, the part of it decoded by the current
+    /// call being `output[self.block_summed..produced]`.
+    fn end_frame_block(&mut self, output: &[u8], produced: usize) -> Result<State> {
+        let block = match self.frame_block.take() {
+            Some(block) => block,
+            None => return Ok(State::frame_header()),
+        };
+        if self.decoded != block.end {
+            return Err(Error::CorruptBlock("content does not match the block size"));
+        }
+        if let Some((expected, mut crc)) = block.checksum {
+            crc.update(&output[self.block_summed..produced]);
+            let actual = crc.sum();
+            if expected != actual {
+                return Err(Error::BlockChecksumMismatch {
+                    offset: block.start,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        self.block_summed = produced;
+        Ok(State::frame_header())
+    }
+
+    /// Returns the state following the end marker.
+    fn end(&self) -> State {
+        if self.checksum.is_some() {
+            State::Trailer {
+                buf: [0; 4],
+                len: 0,
+            }
+        } else {
+            State::Done
+        }
+    }
+
+    /// Checks the codeword with `distance` and `length` that was just read, which ends after
+    /// `consumed` bytes, and returns the state expanding it.
+    fn decode_token(&mut self, distance: usize, length: usize, consumed: u64) -> Result<State> {
+        if !self.options.raw && distance != 0 && length == 0 {
+            if self.framed {
+                return Err(Error::CorruptBlock("end marker inside a block"));
+            }
+            return Ok(self.end());
+        }
+        if length > self.format.max_len() {
+            return Err(Error::MatchTooLong {
+                offset: self.decoded,
+                length,
+            });
+        }
+        // A literal run reads its bytes from the stream, a match only the literal following it.
+        let token_bytes = if distance == 0 { length + 1 } else { 1 };
+        self.options
+            .check_output_limit(self.decoded + length as u64 + 1, consumed + token_bytes as u64)?;
+        if distance == 0 {
+            return Ok(State::Literals(length + 1));
+        }
+        self.check_distance(distance)?;
+        Ok(State::Match {
+            distance,
+            remaining: length,
+        })
+    }
+
+    fn check_distance(&self, distance: usize) -> Result<()> {
+        if distance > self.window.len() {
+            return Err(Error::DistanceBeyondWindow {
+                offset: self.decoded,
+                distance,
+                window_len: self.window.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Decodes the next symbol of the current block into `output[*produced]`, or returns the
+    /// state following it.
+    fn decode_symbol(&mut self,
+                     consumed: u64,
+                     output: &mut [u8],
+                     produced: &mut usize)
+                     -> Result<State> {
+        let symbol = match self.block {
+            Some(ref mut block) => block.next_symbol()?,
+            None => None,
+        };
+        match symbol {
+            None => {
+                self.block = None;
+                Ok(State::block_size())
+            }
+            Some(Symbol::Literal(byte)) => {
+                self.options.check_output_limit(self.decoded + 1, consumed)?;
+                self.window.push(byte);
+                output[*produced] = byte;
+                *produced += 1;
+                self.decoded += 1;
+                Ok(State::Symbols)
+            }
+            Some(Symbol::Match { length, distance }) => {
+                if length > self.format.max_len() {
+                    return Err(Error::MatchTooLong {
+                        offset: self.decoded,
+                        length,
+                    });
+                }
+                self.options.check_output_limit(self.decoded + length as u64, consumed)?;
+                self.check_distance(distance)?;
+                Ok(State::Match {
+                    distance,
+                    remaining: length,
+                })
+            }
+        }
+    }
+
+    fn run(&mut self,
+           stream: &[u8],
+           consumed: &mut usize,
+           output: &mut [u8],
+           produced: &mut usize)
+           -> Result<Status> {
+        loop {
+            let total_consumed = self.consumed + *consumed as u64;
+            let block_ended = self.frame_block
+                .as_ref()
+                .is_some_and(|block| block.input_end == total_consumed);
+            if block_ended {
+                match self.state {
+                    State::Token { len: 0, .. } |
+                    State::BlockSize { len: 0, .. } => {
+                        self.state = self.end_frame_block(output, *produced)?;
+                    }
+                    State::Match { .. } |
+                    State::Symbols |
+                    State::Literals(0) => {}
+                    _ => return Err(Error::CorruptBlock("block ends inside a token")),
+                }
+            }
+            // The tokens of a block are only read up to its end.
+            let input = match self.frame_block {
+                Some(ref block) => {
+                    let end = (block.input_end - self.consumed) as usize;
+                    &stream[..cmp::min(end, stream.len())]
+                }
+                None => stream,
+            };
+            let state = mem::replace(&mut self.state, State::Done);
+            self.state = match state {
+                State::Header { .. } if self.options.raw => {
+                    let varint = self.options.varint();
+                    self.init_window(varint)?;
+                    State::token()
+                }
+                State::Header { mut buf, mut len } => {
+                    if !fill(input, consumed, &mut buf, &mut len) {
+                        self.state = State::Header { buf, len };
+                        return Ok(Status::NeedsInput);
+                    }
+                    self.read_header(&buf)?
+                }
+                State::DictionaryId { mut buf, mut len } => {
+                    if !fill(input, consumed, &mut buf, &mut len) {
+                        self.state = State::DictionaryId { buf, len };
+                        return Ok(Status::NeedsInput);
+                    }
+                    self.check_dictionary(Some(u32::from_be_bytes(buf)))?
+                }
+                State::Token { mut buf, mut len } => {
+                    // The size of a variable-length codeword is only known after reading it, so
+                    // it is collected byte by byte.
+                    let token = loop {
+                        if let Some((distance, length, _)) = self.format.parse(&buf[..len])? {
+                            break Some((distance, length));
+                        }
+                        if *consumed == input.len() {
+                            break None;
+                        }
+                        buf[len] = input[*consumed];
+                        len += 1;
+                        *consumed += 1;
+                    };
+                    match token {
+                        Some((distance, length)) => {
+                            let total_consumed = self.consumed + *consumed as u64;
+                            self.decode_token(distance, length, total_consumed)?
+                        }
+                        None => {
+                            self.state = State::Token { buf, len };
+                            return Ok(Status::NeedsInput);
+                        }
+                    }
+                }
+                State::Literals(0) => State::token(),
+                State::Literals(remaining) => {
+                    let n = cmp::min(cmp::min(remaining, input.len() - *consumed),
+                                     output.len() - *produced);
+                    if n == 0 {
+                        self.state = State::Literals(remaining);
+                        return Ok(if *produced == output.len() {
+                            Status::OutputFull
+                        } else {
+                            Status::NeedsInput
+                        });
+                    }
+                    let bytes = &input[*consumed..(*consumed + n)];
+                    output[*produced..(*produced + n)].copy_from_slice(bytes);
+                    self.window.push_slice(bytes);
+                    *consumed += n;
+                    *produced += n;
+                    self.decoded += n as u64;
+                    State::Literals(remaining - n)
+                }
+                State::Match { remaining: 0, .. } if self.huffman => State::Symbols,
+                // A codeword match is followed by a literal.
+                State::Match { remaining: 0, .. } => State::Literals(1),
+                State::Match { distance, remaining } => {
+                    let n = cmp::min(remaining, output.len() - *produced);
+                    if n == 0 {
+                        self.state = State::Match { distance, remaining };
+                        return Ok(Status::OutputFull);
+                    }
+                    for out in &mut output[*produced..(*produced + n)] {
+                        let idx = self.window.len() - distance;
+                        let c = self.window[idx];
+                        self.window.push(c);
+                        *out = c;
+                    }
+                    *produced += n;
+                    self.decoded += n as u64;
+                    State::Match {
+                        distance,
+                        remaining: remaining - n,
+                    }
+                }
+                State::BlockSize { mut buf, mut len } => {
+                    if !fill(input, consumed, &mut buf, &mut len) {
+                        self.state = State::BlockSize { buf, len };
+                        return Ok(Status::NeedsInput);
+                    }
+                    let size = u32::from_be_bytes(buf) as usize;
+                    if size == 0 && self.framed {
+                        return Err(Error::CorruptBlock("end marker inside a block"));
+                    } else if size == 0 {
+                        self.end()
+                    } else if size > huffman::MAX_BLOCK_SIZE {
+                        return Err(Error::CorruptBlock("block too large"));
+                    } else {
+                        State::BlockPayload {
+                            payload: vec![0; size],
+                            len: 0,
+                        }
+                    }
+                }
+                State::BlockPayload { mut payload, mut len } => {
+                    if !fill(input, consumed, &mut payload, &mut len) {
+                        self.state = State::BlockPayload { payload, len };
+                        return Ok(Status::NeedsInput);
+                    }
+                    self.block = Some(BlockDecoder::new(payload)?);
+                    State::Symbols
+                }
+                State::Symbols => {
+                    if *produced == output.len() {
+                        self.state = State::Symbols;
+                        return Ok(Status::OutputFull);
+                    }
+                    self.decode_symbol(total_consumed, output, produced)?
+                }
+                State::FrameHeader { mut buf, mut len } => {
+                    // The compressed size comes first, zero ends the blocks.
+                    if len < 4 && !fill(input, consumed, &mut buf[..4], &mut len) {
+                        self.state = State::FrameHeader { buf, len };
+                        return Ok(Status::NeedsInput);
+                    }
+                    if buf[..4] == [0; 4] && self.block_index {
+                        State::IndexStart {
+                            buf: [0; 4],
+                            len: 0,
+                        }
+                    } else if buf[..4] == [0; 4] {
+                        self.end()
+                    } else {
+                        let size = BlockHeader::size(self.block_checksums);
+                        if !fill(input, consumed, &mut buf[..size], &mut len) {
+                            self.state = State::FrameHeader { buf, len };
+                            return Ok(Status::NeedsInput);
+                        }
+                        let header = BlockHeader::parse(&buf[..size], self.block_checksums);
+                        let total_consumed = self.consumed + *consumed as u64;
+                        self.block_summed = *produced;
+                        self.start_frame_block(header, total_consumed)
+                    }
+                }
+                State::IndexStart { mut buf, mut len } => {
+                    if !fill(input, consumed, &mut buf, &mut len) {
+                        self.state = State::IndexStart { buf, len };
+                        return Ok(Status::NeedsInput);
+                    }
+                    let count = u32::from_be_bytes(buf);
+                    State::IndexEntries {
+                        remaining: count as u64 * INDEX_ENTRY_SIZE as u64,
+                        count,
+                    }
+                }
+                // The index is only needed for random access, a sequential decoder skips it.
+                State::IndexEntries { remaining: 0, count } => {
+                    State::IndexFooter {
+                        buf: [0; INDEX_FOOTER_SIZE],
+                        len: 0,
+                        count,
+                    }
+                }
+                State::IndexEntries { remaining, count } => {
+                    let n = cmp::min(remaining, (input.len() - *consumed) as u64);
+                    *consumed += n as usize;
+                    self.state = State::IndexEntries {
+                        remaining: remaining - n,
+                        count,
+                    };
+                    if n == 0 {
+                        return Ok(Status::NeedsInput);
+                    }
+                    continue;
+                }
+                State::IndexFooter { mut buf, mut len, count } => {
+                    if !fill(input, consumed, &mut buf, &mut len) {
+                        self.state = State::IndexFooter { buf, len, count };
+                        return Ok(Status::NeedsInput);
+                    }
+                    if frame::read_u64(&buf, 0) != self.decoded ||
+                       frame::read_u32(&buf, 8) != count {
+                        return Err(Error::CorruptBlock("block index does not match the blocks"));
+                    }
+                    self.end()
+                }
+                State::Trailer { mut buf, mut len } => {
+                    if !fill(input, consumed, &mut buf, &mut len) {
+                        self.state = State::Trailer { buf, len };
+                        return Ok(Status::NeedsInput);
+                    }
+                    if let Some(ref mut crc) = self.checksum {
+                        // The bytes produced by this call are not part of the sum yet.
+                        crc.update(&output[..*produced]);
+                        let expected = u32::from_be_bytes(buf);
+                        let actual = crc.sum();
+                        if expected != actual {
+                            return Err(Error::ChecksumMismatch { expected, actual });
+                        }
+                    }
+                    self.checksum = None;
+                    State::Done
+                }
+                State::Done => return Ok(Status::Done),
+                State::Failed(err) => return Err(err),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decompressor, Status};
+    use ::{compress, Lz77Options};
+    use errors::Error;
+    use test_util::sample_data;
+
+    /// Feeds `input` in chunks of `chunk` bytes and collects the output in chunks of `out`
+    /// bytes, returning the last status.
+    fn decompress_chunked(decompressor: &mut Decompressor,
+                          input: &[u8],
+                          chunk: usize,
+                          out: usize)
+                          -> (Vec<u8>, Status) {
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; out];
+        let mut pos = 0;
+        loop {
+            let end = ::std::cmp::min(pos + chunk, input.len());
+            let (consumed, produced, status) = decompressor.decompress(&input[pos..end], &mut buf);
+            pos += consumed;
+            output.extend_from_slice(&buf[..produced]);
+            match status {
+                Status::NeedsInput if pos < input.len() => {}
+                Status::OutputFull => {}
+                status => return (output, status),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompress_in_chunks() {
+        let data = sample_data(20000);
+        let options = [Lz77Options::default(),
+                       Lz77Options { huffman: true, ..Default::default() },
+                       Lz77Options { window_bits: 18, ..Default::default() },
+                       Lz77Options { raw: true, ..Default::default() },
+                       Lz77Options {
+                           block_size: Some(3000),
+                           block_checksum: true,
+                           ..Default::default()
+                       },
+                       Lz77Options {
+                           block_size: Some(1000),
+                           linked_blocks: true,
+                           huffman: true,
+                           ..Default::default()
+                       }];
+        for opts in &options {
+            let compressed = compress(&data, opts);
+            for &(chunk, out) in &[(1, 1), (3, 5), (7, 4096), (1000, 1), (1 << 20, 1 << 20)] {
+                let mut decompressor = Decompressor::new(opts.clone());
+                let (output, status) = decompress_chunked(&mut decompressor,
+                                                          &compressed,
+                                                          chunk,
+                                                          out);
+                assert_eq!(output, data);
+                if opts.raw {
+                    assert_eq!(status, Status::NeedsInput);
+                    assert_eq!(decompressor.finish(), Ok(()));
+                } else {
+                    assert_eq!(status, Status::Done);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_done_leaves_trailing_input() {
+        let data = sample_data(1000);
+        let mut compressed = compress(&data, &Lz77Options::default());
+        let len = compressed.len();
+        compressed.extend_from_slice(b"trailing");
+        let mut decompressor = Decompressor::new(Lz77Options::default());
+        let mut output = vec![0u8; 2000];
+        assert_eq!(decompressor.decompress(&compressed, &mut output),
+                   (len, 1000, Status::Done));
+        assert_eq!(&output[..1000], &data[..]);
+        assert_eq!(decompressor.decompress(&compressed[len..], &mut output),
+                   (0, 0, Status::Done));
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let compressed = compress(&sample_data(1000), &Lz77Options::default());
+        let mut output = vec![0u8; 2000];
+        for &(len, ref err) in &[(3, Error::TruncatedStream),
+                                 (9, Error::TruncatedToken),
+                                 (compressed.len() - 2, Error::TruncatedStream)] {
+            let mut decompressor = Decompressor::new(Lz77Options::default());
+            let (consumed, _, status) = decompressor.decompress(&compressed[..len], &mut output);
+            assert_eq!((consumed, status), (len, Status::NeedsInput));
+            assert_eq!(decompressor.finish(), Err(err.clone()));
+            assert!(decompressor.is_done());
+        }
+    }
+
+    #[test]
+    fn test_failure_is_sticky() {
+        let mut compressed = compress(&sample_data(1000), &Lz77Options::default());
+        let last = compressed.len() - 1;
+        compressed[last] ^= 0xff;
+        let mut decompressor = Decompressor::new(Lz77Options::default());
+        let mut output = vec![0u8; 2000];
+        let (_, produced, status) = decompressor.decompress(&compressed, &mut output);
+        assert_eq!(produced, 1000);
+        match status {
+            Status::Failed(Error::ChecksumMismatch { .. }) => {}
+            other => panic!("Unexpected status {:?}", other),
+        }
+        let (consumed, produced, again) = decompressor.decompress(&compressed, &mut output);
+        assert_eq!((consumed, produced, again), (0, 0, status));
+    }
+}
@@ -0,0 +1,172 @@
+//! Preset dictionaries, which prime the window of an encoder and decoder with content that
+//! is likely to occur in the data, so even short streams find matches.
+//!
+//! Only the last `2^window_bits - 1` bytes of a dictionary fit into the window, the rest is
+//! ignored. `train` builds a dictionary from samples of the data to compress.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use checksum::Crc32;
+
+/// Length of the substrings whose frequency is counted while training.
+const DMER_LEN: usize = 8;
+/// Length of the segments a trained dictionary is made of.
+const SEGMENT_LEN: usize = 64;
+
+/// Returns the ID of `dictionary`, its CRC-32, which the stream header records so a decoder
+/// can tell whether it was given the dictionary the stream was encoded with.
+pub fn id(dictionary: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(dictionary);
+    crc.sum()
+}
+
+/// Builds a dictionary of at most `max_size` bytes from substrings that occur in many of
+/// `samples`. To fit the window of streams encoded with it, `max_size` should not exceed
+/// `2^window_bits - 1`.
+///
+/// The samples are split into one range per 64 bytes of the dictionary, and the 64-byte
+/// segment in each range made of the substrings shared with the most other samples is picked.
+/// Substrings already picked do not count again. The segments are ordered so the most valuable
+/// ones end up at the end of the dictionary, closest to the data. Content found in a single
+/// sample is never picked, so fewer than two samples give an empty dictionary.
+pub fn train(samples: &[&[u8]], max_size: usize) -> Vec<u8> {
+    // The number of samples each dmer occurs in, repetitions within a sample are matched
+    // without a dictionary.
+    let mut ids = HashMap::new();
+    let mut frequencies: Vec<u32> = Vec::new();
+    let dmers: Vec<Vec<usize>> = samples.iter()
+        .map(|sample| {
+            let mut seen = HashSet::new();
+            sample.windows(DMER_LEN)
+                .map(|dmer| {
+                    let next = frequencies.len();
+                    let id = *ids.entry(dmer).or_insert(next);
+                    if id == next {
+                        frequencies.push(0);
+                    }
+                    if seen.insert(id) {
+                        frequencies[id] += 1;
+                    }
+                    id
+                })
+                .collect()
+        })
+        .collect();
+
+    let total: usize = dmers.iter().map(|dmers| dmers.len()).sum();
+    let segments = cmp::max(max_size / SEGMENT_LEN, 1);
+    if total == 0 || max_size == 0 {
+        return Vec::new();
+    }
+    let range_len = total.div_ceil(cmp::min(segments, total));
+    let window = SEGMENT_LEN - DMER_LEN + 1;
+    let weight = |frequencies: &[u32], id: usize| frequencies[id].saturating_sub(1) as u64;
+
+    let mut picked = Vec::new();
+    let mut range_start = 0;
+    while range_start < total {
+        let range_end = cmp::min(range_start + range_len, total);
+        // Score, sample and start of the best segment in the range.
+        let mut best: Option<(u64, usize, usize)> = None;
+        let mut base = 0;
+        for (idx, sample_dmers) in dmers.iter().enumerate() {
+            if base >= range_end {
+                break;
+            }
+            let lo = cmp::max(range_start, base) - base;
+            let hi = cmp::min(range_end, base + sample_dmers.len()).saturating_sub(base);
+            base += sample_dmers.len();
+            if lo >= hi {
+                continue;
+            }
+            let end = cmp::min(lo + window, sample_dmers.len());
+            let mut score: u64 =
+                sample_dmers[lo..end].iter().map(|&id| weight(&frequencies, id)).sum();
+            for start in lo..hi {
+                if start > lo {
+                    score -= weight(&frequencies, sample_dmers[start - 1]);
+                    if let Some(&id) = sample_dmers.get(start + window - 1) {
+                        score += weight(&frequencies, id);
+                    }
+                }
+                if score > 0 && best.is_none_or(|(best_score, _, _)| score > best_score) {
+                    best = Some((score, idx, start));
+                }
+            }
+        }
+        if let Some((score, idx, start)) = best {
+            // Drops the head and tail of the segment that add nothing, as they were picked
+            // before or are unique to the sample.
+            let segment_dmers = &dmers[idx][start..cmp::min(start + window, dmers[idx].len())];
+            let first = segment_dmers.iter().position(|&id| weight(&frequencies, id) > 0);
+            let last = segment_dmers.iter().rposition(|&id| weight(&frequencies, id) > 0);
+            if let (Some(first), Some(last)) = (first, last) {
+                for &id in segment_dmers {
+                    frequencies[id] = 0;
+                }
+                picked.push((score, &samples[idx][(start + first)..(start + last + DMER_LEN)]));
+            }
+        }
+        range_start = range_end;
+    }
+
+    picked.sort_by_key(|&(score, _)| score);
+    let mut dictionary: Vec<u8> =
+        picked.iter().flat_map(|&(_, segment)| segment.iter().cloned()).collect();
+    if dictionary.len() > max_size {
+        dictionary.drain(..(dictionary.len() - max_size));
+    }
+    dictionary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{train, SEGMENT_LEN};
+    use std::io::Write;
+    use ::{compress, AnySearcher, Lz77Encoder, Lz77Options};
+
+    fn log_line(n: u32) -> Vec<u8> {
+        format!("{} INFO request handled method=GET path=/api/v1/items/{} status=200 \
+                 duration_ms={} user_agent=\"curl/7.68.0\"\n",
+                1600000000 + n * 37,
+                n * 11 % 500,
+                n * 7 % 90)
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_train() {
+        let samples: Vec<Vec<u8>> = (0..200).map(log_line).collect();
+        let samples: Vec<&[u8]> = samples.iter().map(|sample| &sample[..]).collect();
+        let opts = Lz77Options::default();
+        let max_size = (1 << opts.window_bits) - 1;
+        let dictionary = train(&samples, max_size);
+        assert!(!dictionary.is_empty() && dictionary.len() <= max_size);
+        assert!(dictionary.windows(12).any(|w| w == b"status=200 d"));
+
+        let message = log_line(1000);
+        let mut encoder = Lz77Encoder::<_, AnySearcher>::with_dictionary(Vec::new(),
+                                                                         opts.clone(),
+                                                                         &dictionary);
+        encoder.write_all(&message).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() * 2 < compress(&message, &opts).len());
+
+        let small = train(&samples, 20);
+        assert!(!small.is_empty() && small.len() <= 20);
+    }
+
+    #[test]
+    fn test_train_without_shared_content() {
+        let sample = log_line(1);
+        assert_eq!(train(&[], 1000), b"");
+        assert_eq!(train(&[&sample], 1000), b"");
+        assert_eq!(train(&[b"short", b"short"], 1000), b"");
+        assert_eq!(train(&[&sample, &sample], 0), b"");
+        // Shared content is picked once, segments only overlap by less than a dmer.
+        let dictionary = train(&[&sample, &sample], 1000);
+        assert!(dictionary.len() >= sample.len() && dictionary.len() < 2 * sample.len());
+        assert!(train(&[&sample, &sample], SEGMENT_LEN).len() <= SEGMENT_LEN);
+    }
+}
@@ -1,111 +1,112 @@
 use std::io;
-use std::io::Write;
 use ::Lz77Options;
 use searcher::Searcher;
-use buffer::{RingBuffer, CombinedBuffer, SizedBuffer};
-use codeword::CodeWord;
+use compressor::{Compressor, FlushMode};
+use decompressor::Status;
 
-enum Lz77EncoderToken {
-    None,
-    Match(usize, usize),
-}
+const INNER_TAKEN: &str = "Inner writer already taken";
+/// Size of the chunks written to the inner writer.
+const OUTPUT_BUFFER_SIZE: usize = 1 << 14;
 
+/// Encodes everything written to it into `W`, using a `Compressor`.
 pub struct Lz77Encoder<W, S>
     where W: io::Write,
           S: Searcher + Default
 {
-    out: W,
-    searcher: S,
-    window: RingBuffer<u8>,
-    unmatched_data: Vec<u8>,
-    forward_search_size: usize,
-    output_buffer: Vec<u8>,
-    options: Lz77Options,
+    out: Option<W>,
+    compressor: Compressor<S>,
+    buf: Vec<u8>,
 }
 
 impl<W, S> Lz77Encoder<W, S>
     where W: io::Write,
           S: Searcher + Default
 {
+    /// Creates an encoder with a default searcher tuned by `options`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
     pub fn new(output: W, options: Lz77Options) -> Self {
-        let size: usize = (1 << options.window_size as usize) - 1;
-        let forward_search_size = 1 << (16 - options.window_size);
-        let ring_buffer = RingBuffer::new(size);
-        let searcher = S::default();
+        let mut searcher = S::default();
+        searcher.configure(&options);
+        Self::with_searcher(output, options, searcher)
+    }
+
+    /// Creates an encoder with a default searcher whose window starts out holding `dictionary`,
+    /// see `Compressor::with_dictionary`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
+    pub fn with_dictionary(output: W, options: Lz77Options, dictionary: &[u8]) -> Self {
         Lz77Encoder {
-            out: output,
-            window: ring_buffer,
-            unmatched_data: Vec::with_capacity(forward_search_size + 1),
-            forward_search_size: forward_search_size,
-            searcher: searcher,
-            output_buffer: Vec::with_capacity(forward_search_size + 1),
-            options: options,
+            out: Some(output),
+            compressor: Compressor::with_dictionary(options, dictionary),
+            buf: vec![0; OUTPUT_BUFFER_SIZE],
         }
     }
 
-    fn fill_forward_buffer(&mut self, buf: &[u8]) -> Option<(usize)> {
-        let mut extra_cap = self.forward_search_size + 1 - self.unmatched_data.len();
-        if extra_cap > buf.len() {
-            extra_cap = buf.len();
-        }
-        for item in &buf[0..extra_cap] {
-            self.unmatched_data.push(*item)
-        }
-        if self.forward_search_size + 1 - self.unmatched_data.len() == 0 {
-            Some(extra_cap)
-        } else {
-            None
+    /// Creates an encoder using the given searcher instance. The searcher is reset, but keeps
+    /// its tuning parameters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`.
+    pub fn with_searcher(output: W, options: Lz77Options, searcher: S) -> Self {
+        Lz77Encoder {
+            out: Some(output),
+            compressor: Compressor::with_searcher(options, searcher),
+            buf: vec![0; OUTPUT_BUFFER_SIZE],
         }
     }
 
-    fn move_unmatched_to_window(&mut self, n: usize) {
-        for _ in 0..n {
-            self.window.push(self.unmatched_data.remove(0));
-        }
+    /// Writes all pending data and the stream trailer, then returns the inner writer.
+    ///
+    /// Unlike dropping the encoder, this reports errors that occur while writing the final
+    /// codewords.
+    pub fn finish(mut self) -> io::Result<W> {
+        let res = self.finish_stream();
+        // Taking the writer keeps `Drop` from retrying after an error.
+        let out = self.out.take().expect(INNER_TAKEN);
+        res.map(|_| out)
     }
 
-    fn write_output_buffer(&mut self) -> io::Result<()> {
-        if self.output_buffer.len() > 0 {
-            let code = CodeWord::new_with_data(16 - self.options.window_size,
-                                               0u16,
-                                               self.output_buffer.len() as u16 - 1)
-                .expect("Somebody screwed up with the CodeWord size");
-            self.out.write_all(&code.as_bytes()[..])?;
-            self.out.write_all(&mut self.output_buffer[..])?;
-            self.output_buffer.clear();
-        }
-        Ok(())
+    /// Returns the inner writer without writing pending data or the stream trailer.
+    pub fn into_inner(mut self) -> W {
+        self.out.take().expect(INNER_TAKEN)
     }
 
-    fn write_to_inner(&mut self, token: Lz77EncoderToken, next: u8) -> io::Result<()> {
-        if self.output_buffer.len() == self.forward_search_size {
-            self.write_output_buffer()?;
-        }
+    pub fn get_ref(&self) -> &W {
+        self.out.as_ref().expect(INNER_TAKEN)
+    }
 
-        match token {
-            Lz77EncoderToken::Match(dist, len) => {
-                if self.output_buffer.len() > 0 {
-                    self.write_output_buffer()?;
-                }
-                let code = match CodeWord::new_with_data(16 - self.options.window_size,
-                                                         dist as u16,
-                                                         len as u16) {
-                    Ok(cw) => cw,
-                    Err(e) => {
-                        panic!("Somebody screwed up with the CodeWord size ({}, {}), Err = {}",
-                               dist,
-                               len,
-                               e);
-                    }
-                };
-                self.out.write_all(&code.as_bytes()[..])?;
-                self.out.write_all(&[next])?;
+    /// Writing to the inner writer directly will corrupt the compressed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.out.as_mut().expect(INNER_TAKEN)
+    }
+
+    /// Passes `input` to the compressor and writes its output until it stops with `until`.
+    fn compress(&mut self, mut input: &[u8], flush: FlushMode, until: Status) -> io::Result<()> {
+        loop {
+            let (consumed, produced, status) =
+                self.compressor.compress(input, &mut self.buf, flush);
+            input = &input[consumed..];
+            self.out.as_mut().expect(INNER_TAKEN).write_all(&self.buf[..produced])?;
+            if let Status::Failed(err) = status {
+                return Err(err.into());
             }
-            Lz77EncoderToken::None => {
-                self.output_buffer.push(next);
+            if status == until {
+                return Ok(());
             }
         }
-        Ok(())
+    }
+
+    /// Writes all pending data followed by the end marker and trailer, which terminate a stream
+    /// that carries a header. Raw streams simply end after the last codeword.
+    fn finish_stream(&mut self) -> io::Result<()> {
+        self.compress(&[], FlushMode::Finish, Status::Done)?;
+        self.get_mut().flush()
     }
 }
 
@@ -113,62 +114,14 @@ impl<W, S> io::Write for Lz77Encoder<W, S>
     where W: io::Write,
           S: Searcher + Default
 {
-    fn write(&mut self, full_buf: &[u8]) -> io::Result<usize> {
-        let size = full_buf.len();
-        let mut buf = full_buf;
-        while let Some(n) = self.fill_forward_buffer(buf) {
-            let search_result = {
-                let search_buf = CombinedBuffer(&self.window, &self.unmatched_data);
-                self.searcher.find_longest_match(&search_buf,
-                                                 &self.unmatched_data[..(self.forward_search_size -
-                                                                         1)])
-            };
-
-            let fw = match search_result {
-                Some(res) => {
-                    let dist = self.window.len() - res.position;
-                    let next = self.unmatched_data[res.length];
-                    self.write_to_inner(Lz77EncoderToken::Match(dist, res.length), next)?;
-                    res.length + 1
-                }
-                None => {
-                    let next = self.unmatched_data[0];
-                    self.write_to_inner(Lz77EncoderToken::None, next)?;
-                    1
-                }
-            };
-
-            self.move_unmatched_to_window(fw);
-            buf = &buf[n..];
-        }
-        Ok(size)
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.compress(buf, FlushMode::None, Status::NeedsInput)?;
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        while self.unmatched_data.len() != 0 {
-            let search_result = {
-                let search_buf = CombinedBuffer(&self.window, &self.unmatched_data);
-                let search_size = self.unmatched_data.len() - 1;
-                self.searcher.find_longest_match(&search_buf, &self.unmatched_data[..(search_size)])
-            };
-
-            let fw = match search_result {
-                Some(res) => {
-                    let dist = self.window.len() - res.position;
-                    let next = self.unmatched_data[res.length];
-                    self.write_to_inner(Lz77EncoderToken::Match(dist, res.length), next)?;
-                    res.length + 1
-                }
-                None => {
-                    let next = self.unmatched_data[0];
-                    self.write_to_inner(Lz77EncoderToken::None, next)?;
-                    1
-                }
-            };
-            self.move_unmatched_to_window(fw);
-        }
-        self.write_output_buffer()?;
-        self.out.flush()
+        self.compress(&[], FlushMode::Sync, Status::NeedsInput)?;
+        self.get_mut().flush()
     }
 }
 
@@ -177,6 +130,8 @@ impl<W, S> Drop for Lz77Encoder<W, S>
           S: Searcher + Default
 {
     fn drop(&mut self) {
-        let _ = self.flush();
+        if self.out.is_some() {
+            let _ = self.finish_stream();
+        }
     }
 }
@@ -1,7 +1,150 @@
 use std::io;
+use std::fmt;
+use std::error;
+use std::result;
 
-error_chain! {
-    foreign_links {
-        io::Error, IOError;
+/// Everything that can go wrong while encoding or decoding, apart from I/O errors of the
+/// underlying reader or writer.
+///
+/// Encoders and decoders implement `io::Write`/`io::Read`, so these are handed out wrapped in
+/// an `io::Error`. The original value can be recovered with `io::Error::get_ref` and
+/// `downcast_ref::<Error>()`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    /// The options can not be used for encoding or decoding.
+    InvalidOptions(&'static str),
+    /// A value does not fit into its codeword field.
+    CodeWordOverflow { field: &'static str, value: usize },
+    /// The stream does not start with the expected magic bytes.
+    InvalidMagic,
+    /// The stream header announces a format version this crate does not know.
+    UnsupportedVersion(u8),
+    /// The stream header announces a window size outside the supported range.
+    InvalidWindowSize(u8),
+    /// The stream header sets flags that can not be combined, like block checksums on a stream
+    /// that is not split into blocks.
+    InvalidFlags(u8),
+    /// A codeword at decoded offset `offset` refers back further than the `window_len` bytes
+    /// decoded so far.
+    DistanceBeyondWindow {
+        offset: u64,
+        distance: usize,
+        window_len: usize,
+    },
+    /// A variable-length codeword does not fit into 32 bits.
+    InvalidVarint,
+    /// A match or literal run at decoded offset `offset` is longer than the stream format
+    /// allows.
+    MatchTooLong { offset: u64, length: usize },
+    /// An entropy-coded block, or a block of a stream split into blocks, can not be decoded.
+    CorruptBlock(&'static str),
+    /// The checksum in the header of the block starting at decoded offset `offset` does not
+    /// match the block's content.
+    BlockChecksumMismatch {
+        offset: u64,
+        expected: u32,
+        actual: u32,
+    },
+    /// The stream was encoded with a different dictionary than the decoder was given, or only
+    /// one of them uses a dictionary. Dictionaries are identified by `dict::id`.
+    DictionaryMismatch {
+        expected: Option<u32>,
+        actual: Option<u32>,
+    },
+    /// The stream has no block index, which random access needs.
+    NoBlockIndex,
+    /// Input was passed to a compressor that already finished its stream.
+    StreamFinished,
+    /// The stream ended in the middle of a token.
+    TruncatedToken,
+    /// The stream ended before its end marker or trailer.
+    TruncatedStream,
+    /// The content checksum in the trailer does not match the decoded content.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Decoding would produce more than `limit` bytes, as set by `max_output_size` or
+    /// `max_expansion_ratio` in `Lz77Options`.
+    OutputLimitExceeded { limit: u64 },
+    /// The output slice passed to `compress_into` or `decompress_into` is too small.
+    OutputTooSmall,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidOptions(reason) => write!(fmt, "Invalid options: {}", reason),
+            Error::CodeWordOverflow { field, value } => {
+                write!(fmt, "Value {} does not fit into the {} field of a codeword", value, field)
+            }
+            Error::InvalidMagic => write!(fmt, "Invalid magic bytes, not an lz77 stream"),
+            Error::UnsupportedVersion(version) => {
+                write!(fmt, "Unsupported lz77 format version {}", version)
+            }
+            Error::InvalidWindowSize(size) => {
+                write!(fmt, "Window size of {} bits is outside the supported range", size)
+            }
+            Error::InvalidFlags(flags) => write!(fmt, "Invalid header flags {:#04x}", flags),
+            Error::DistanceBeyondWindow { offset, distance, window_len } => {
+                write!(fmt,
+                       "Codeword at offset {} refers {} bytes back, but only {} bytes are in \
+                        the window",
+                       offset,
+                       distance,
+                       window_len)
+            }
+            Error::InvalidVarint => write!(fmt, "Variable-length codeword is too long"),
+            Error::MatchTooLong { offset, length } => {
+                write!(fmt, "Token of {} bytes at offset {} is too long", length, offset)
+            }
+            Error::CorruptBlock(reason) => write!(fmt, "Corrupt block: {}", reason),
+            Error::BlockChecksumMismatch { offset, expected, actual } => {
+                write!(fmt,
+                       "Checksum mismatch in block at offset {}: expected {:#010x}, got {:#010x}",
+                       offset,
+                       expected,
+                       actual)
+            }
+            Error::DictionaryMismatch { expected, actual } => {
+                let name = |id: Option<u32>| {
+                    id.map_or("no dictionary".to_string(), |id| format!("dictionary {:#010x}", id))
+                };
+                write!(fmt,
+                       "Dictionary mismatch: stream needs {}, decoder has {}",
+                       name(expected),
+                       name(actual))
+            }
+            Error::NoBlockIndex => write!(fmt, "Stream has no block index"),
+            Error::StreamFinished => write!(fmt, "Stream was already finished"),
+            Error::TruncatedToken => write!(fmt, "Stream ended in the middle of a token"),
+            Error::TruncatedStream => write!(fmt, "Stream ended before its end marker"),
+            Error::ChecksumMismatch { expected, actual } => {
+                write!(fmt,
+                       "Content checksum mismatch: expected {:#010x}, got {:#010x}",
+                       expected,
+                       actual)
+            }
+            Error::OutputLimitExceeded { limit } => {
+                write!(fmt, "Decoded content exceeds the limit of {} bytes", limit)
+            }
+            Error::OutputTooSmall => write!(fmt, "Output buffer is too small"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::InvalidOptions(_) |
+            Error::CodeWordOverflow { .. } |
+            Error::StreamFinished => io::ErrorKind::InvalidInput,
+            Error::TruncatedToken |
+            Error::TruncatedStream => io::ErrorKind::UnexpectedEof,
+            Error::OutputTooSmall => io::ErrorKind::WriteZero,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
     }
 }
@@ -0,0 +1,321 @@
+use std::cmp;
+use errors::{Error, Result};
+use ::{Lz77Options, MAX_MATCH_LEN};
+use codeword::CodeWordFormat;
+use decompressor::{Decompressor, Status};
+use header::{Header, FLAG_HUFFMAN, FLAG_VARINT};
+use huffman;
+
+/// Size of a block header without a checksum.
+pub const BLOCK_HEADER_SIZE: usize = 8;
+/// Size of a block header with a checksum.
+pub const MAX_BLOCK_HEADER_SIZE: usize = 12;
+/// Written in place of a block header after the last block, a compressed size of zero.
+pub const END_OF_BLOCKS: [u8; 4] = [0; 4];
+/// Size of an entry of the block index.
+pub const INDEX_ENTRY_SIZE: usize = 16;
+/// Size of the content size and entry count ending the block index.
+pub const INDEX_FOOTER_SIZE: usize = 12;
+/// Output decoded into by `decode_block` at first, doubled as the block turns out larger.
+const INITIAL_BLOCK_OUTPUT: usize = 1 << 16;
+
+/// Header in front of every block of a stream split into blocks.
+///
+/// Layout: compressed size, uncompressed size and, if the stream header sets
+/// `FLAG_BLOCK_CHECKSUM`, a CRC-32 of the block's content, all as big-endian 32-bit integers.
+/// The compressed block follows, its tokens written like those of a stream without blocks but
+/// without an end marker.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BlockHeader {
+    pub compressed_size: usize,
+    pub uncompressed_size: usize,
+    pub checksum: Option<u32>,
+}
+
+impl BlockHeader {
+    /// Returns the size of a block header in a stream with or without block checksums.
+    pub fn size(checksum: bool) -> usize {
+        if checksum {
+            MAX_BLOCK_HEADER_SIZE
+        } else {
+            BLOCK_HEADER_SIZE
+        }
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.compressed_size as u32).to_be_bytes());
+        out.extend_from_slice(&(self.uncompressed_size as u32).to_be_bytes());
+        if let Some(sum) = self.checksum {
+            out.extend_from_slice(&sum.to_be_bytes());
+        }
+    }
+
+    /// Parses a header of `BlockHeader::size(checksum)` bytes.
+    pub fn parse(buf: &[u8], checksum: bool) -> Self {
+        BlockHeader {
+            compressed_size: read_u32(buf, 0) as usize,
+            uncompressed_size: read_u32(buf, 4) as usize,
+            checksum: if checksum { Some(read_u32(buf, 8)) } else { None },
+        }
+    }
+}
+
+/// Position of a block in the content and in the compressed stream.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct IndexEntry {
+    pub uncompressed_offset: u64,
+    /// Offset of the block header from the start of the stream header.
+    pub compressed_offset: u64,
+}
+
+/// Index of the blocks of a stream, written after the end of blocks if the stream header sets
+/// `FLAG_BLOCK_INDEX`.
+///
+/// Layout: number of entries as a big-endian 32-bit integer, the entries as pairs of
+/// uncompressed and compressed offset, the content size, and the number of entries again, so the
+/// index can be found from the end of the stream. The offsets and content size are big-endian
+/// 64-bit integers.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct BlockIndex {
+    pub entries: Vec<IndexEntry>,
+    pub content_size: u64,
+}
+
+impl BlockIndex {
+    /// Adds a block of `uncompressed_size` bytes written at `compressed_offset`.
+    pub fn push(&mut self, compressed_offset: u64, uncompressed_size: usize) {
+        self.entries.push(IndexEntry {
+            uncompressed_offset: self.content_size,
+            compressed_offset,
+        });
+        self.content_size += uncompressed_size as u64;
+    }
+
+    /// Returns the size of the index of `blocks` blocks.
+    pub fn size(blocks: usize) -> usize {
+        4 + blocks * INDEX_ENTRY_SIZE + INDEX_FOOTER_SIZE
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let count = (self.entries.len() as u32).to_be_bytes();
+        out.extend_from_slice(&count);
+        for entry in &self.entries {
+            out.extend_from_slice(&entry.uncompressed_offset.to_be_bytes());
+            out.extend_from_slice(&entry.compressed_offset.to_be_bytes());
+        }
+        out.extend_from_slice(&self.content_size.to_be_bytes());
+        out.extend_from_slice(&count);
+    }
+
+    /// Parses a complete index, checking that its offsets increase.
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let corrupt = Error::CorruptBlock("invalid block index");
+        if buf.len() < BlockIndex::size(0) {
+            return Err(corrupt);
+        }
+        let count = read_u32(buf, 0) as usize;
+        if buf.len() != BlockIndex::size(count) || read_u32(buf, buf.len() - 4) as usize != count {
+            return Err(corrupt);
+        }
+        let mut index = BlockIndex {
+            entries: Vec::with_capacity(count),
+            content_size: read_u64(buf, buf.len() - INDEX_FOOTER_SIZE),
+        };
+        for i in 0..count {
+            let pos = 4 + i * INDEX_ENTRY_SIZE;
+            let entry = IndexEntry {
+                uncompressed_offset: read_u64(buf, pos),
+                compressed_offset: read_u64(buf, pos + 8),
+            };
+            let ordered = match index.entries.last() {
+                Some(last) => {
+                    entry.uncompressed_offset > last.uncompressed_offset &&
+                    entry.compressed_offset > last.compressed_offset
+                }
+                None => entry.uncompressed_offset == 0,
+            };
+            if !ordered || entry.uncompressed_offset >= index.content_size {
+                return Err(corrupt);
+            }
+            index.entries.push(entry);
+        }
+        Ok(index)
+    }
+
+    /// Returns the index of the block holding content offset `pos`, if there is one.
+    pub fn find(&self, pos: u64) -> Option<usize> {
+        if pos >= self.content_size {
+            return None;
+        }
+        Some(self.entries.partition_point(|entry| entry.uncompressed_offset <= pos) - 1)
+    }
+
+    /// Returns the content offsets of the start and end of block `idx`.
+    pub fn block_range(&self, idx: usize) -> (u64, u64) {
+        let end = self.entries.get(idx + 1).map_or(self.content_size, |e| e.uncompressed_offset);
+        (self.entries[idx].uncompressed_offset, end)
+    }
+}
+
+/// Decodes the content of the block of `size` bytes starting at content offset `start`, given a
+/// stream consisting of just that block without index or trailer.
+pub fn decode_block(stream: &[u8], start: u64, size: usize) -> Result<Vec<u8>> {
+    let mut decompressor = Decompressor::new(Lz77Options::default());
+    // The output grows with the content, so a block header claiming a large size does not
+    // allocate it. The spare byte keeps the decompressor from stopping before the end of blocks.
+    let mut output = vec![0; cmp::min(size, INITIAL_BLOCK_OUTPUT) + 1];
+    let (mut consumed, mut produced) = (0, 0);
+    loop {
+        let (read, written, status) = decompressor.decompress(&stream[consumed..],
+                                                              &mut output[produced..]);
+        consumed += read;
+        produced += written;
+        match status {
+            Status::OutputFull if output.len() <= size => {
+                let len = cmp::min(2 * output.len(), size + 1);
+                output.resize(len, 0);
+            }
+            Status::Done if consumed == stream.len() && produced == size => {
+                output.truncate(produced);
+                return Ok(output);
+            }
+            Status::Failed(err) => return Err(at_offset(err, start)),
+            _ => return Err(Error::CorruptBlock("content does not match the block size")),
+        }
+    }
+}
+
+/// Returns the largest compressed size of a block of `uncompressed_size` bytes in a stream with
+/// `header`, whatever match lengths it was encoded with. A block header claiming more can only
+/// come from a corrupt stream.
+pub fn max_compressed_size(uncompressed_size: usize, header: &Header) -> u64 {
+    if header.has_flag(FLAG_HUFFMAN) {
+        return huffman::compress_bound(uncompressed_size, 1, header.window_bits, MAX_MATCH_LEN) as
+               u64;
+    }
+    // A byte costs at most itself and a codeword, as a literal run of its own or as half of a
+    // one-byte match followed by a literal. Three more codewords cover the end of the tokens.
+    let format = CodeWordFormat::new(header.window_bits, header.has_flag(FLAG_VARINT));
+    let codeword = format.max_size() as u64;
+    uncompressed_size as u64 * (codeword + 1) + 3 * codeword
+}
+
+/// Moves the offset of an error found while decoding a block on its own to where the block
+/// starts in the content of the stream.
+fn at_offset(err: Error, start: u64) -> Error {
+    match err {
+        Error::DistanceBeyondWindow { offset, distance, window_len } => {
+            Error::DistanceBeyondWindow {
+                offset: start + offset,
+                distance,
+                window_len,
+            }
+        }
+        Error::MatchTooLong { offset, length } => {
+            Error::MatchTooLong {
+                offset: start + offset,
+                length,
+            }
+        }
+        Error::BlockChecksumMismatch { offset, expected, actual } => {
+            Error::BlockChecksumMismatch {
+                offset: start + offset,
+                expected,
+                actual,
+            }
+        }
+        err => err,
+    }
+}
+
+pub fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[pos..(pos + 4)]);
+    u32::from_be_bytes(bytes)
+}
+
+pub fn read_u64(buf: &[u8], pos: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[pos..(pos + 8)]);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use header::HEADER_SIZE;
+
+    #[test]
+    fn test_block_header_round_trip() {
+        for &checksum in &[None, Some(0xdeadbeef)] {
+            let header = BlockHeader {
+                compressed_size: 1234,
+                uncompressed_size: 1 << 30,
+                checksum,
+            };
+            let mut buf = Vec::new();
+            header.write(&mut buf);
+            assert_eq!(buf.len(), BlockHeader::size(checksum.is_some()));
+            assert_eq!(BlockHeader::parse(&buf, checksum.is_some()), header);
+        }
+    }
+
+    #[test]
+    fn test_block_index_round_trip() {
+        let mut index = BlockIndex::default();
+        index.push(7, 1000);
+        index.push(300, 1000);
+        index.push(650, 1);
+        let mut buf = Vec::new();
+        index.write(&mut buf);
+        assert_eq!(buf.len(), BlockIndex::size(3));
+        let parsed = BlockIndex::parse(&buf).unwrap();
+        assert_eq!(parsed, index);
+        assert_eq!(parsed.find(0), Some(0));
+        assert_eq!(parsed.find(1999), Some(1));
+        assert_eq!(parsed.find(2000), Some(2));
+        assert_eq!(parsed.find(2001), None);
+        assert_eq!(parsed.block_range(1), (1000, 2000));
+        assert_eq!(parsed.block_range(2), (2000, 2001));
+
+        let corrupt = Err(Error::CorruptBlock("invalid block index"));
+        assert_eq!(BlockIndex::parse(&buf[1..]), corrupt);
+        let mut unordered = buf.clone();
+        let (first, second) = (4, 4 + INDEX_ENTRY_SIZE);
+        unordered.copy_within(first..second, second);
+        assert_eq!(BlockIndex::parse(&unordered), corrupt);
+    }
+
+    #[test]
+    fn test_max_compressed_size() {
+        let mut state: u32 = 3;
+        let noise: Vec<u8> = (0..20000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 24) as u8
+            })
+            .collect();
+        let repeats: Vec<u8> = noise.iter().map(|b| b % 3).collect();
+        let options = [Lz77Options::default(),
+                       Lz77Options { min_match_len: Some(1), ..Default::default() },
+                       Lz77Options { max_match_len: Some(1), ..Default::default() },
+                       Lz77Options { window_bits: 20, ..Default::default() },
+                       Lz77Options { huffman: true, min_match_len: Some(1), ..Default::default() }];
+        for opts in &options {
+            let opts = Lz77Options { block_size: Some(1000), ..opts.clone() };
+            for data in &[&noise, &repeats] {
+                let compressed = ::compress(data, &opts);
+                let mut buf = [0; HEADER_SIZE];
+                buf.copy_from_slice(&compressed[..HEADER_SIZE]);
+                let header = Header::parse(&buf).unwrap();
+                let mut pos = HEADER_SIZE;
+                while compressed[pos..(pos + 4)] != END_OF_BLOCKS {
+                    let block = BlockHeader::parse(&compressed[pos..], false);
+                    assert!(block.compressed_size as u64 <=
+                            max_compressed_size(block.uncompressed_size, &header));
+                    pos += BLOCK_HEADER_SIZE + block.compressed_size;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use errors::{Error, Result};
+use ::{Lz77Options, MIN_WINDOW_BITS, MAX_WINDOW_BITS, MAX_CODEWORD_WINDOW_BITS};
+
+pub const MAGIC: [u8; 4] = *b"LZ77";
+pub const VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 7;
+
+/// A CRC-32 of the uncompressed content follows the end marker.
+pub const FLAG_CHECKSUM: u8 = 0x01;
+
+/// The tokens are written as entropy-coded blocks instead of codewords.
+pub const FLAG_HUFFMAN: u8 = 0x02;
+
+/// Codewords are variable-length, allowing larger windows and longer matches.
+pub const FLAG_VARINT: u8 = 0x04;
+
+/// The content is split into blocks, each preceded by a block header.
+pub const FLAG_BLOCKS: u8 = 0x08;
+
+/// Every block header carries a CRC-32 of the block's content.
+pub const FLAG_BLOCK_CHECKSUM: u8 = 0x10;
+
+/// Matches may reach back into previous blocks, which then have to be decoded first.
+pub const FLAG_LINKED_BLOCKS: u8 = 0x20;
+
+/// An index of the blocks follows the end of the blocks.
+pub const FLAG_BLOCK_INDEX: u8 = 0x40;
+
+/// The window starts out holding a preset dictionary, whose ID follows the header.
+pub const FLAG_DICTIONARY: u8 = 0x80;
+
+/// Size of the dictionary ID following the header if `FLAG_DICTIONARY` is set.
+pub const DICTIONARY_ID_SIZE: usize = 4;
+
+/// Self-describing stream header written in front of the codewords unless raw mode is used.
+///
+/// Layout: 4 magic bytes, format version, window size in bits, flags. A stream encoded with a
+/// dictionary continues with the dictionary ID as a big-endian 32-bit integer.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Header {
+    pub window_bits: u8,
+    pub flags: u8,
+}
+
+impl Header {
+    /// Returns the header describing a stream encoded with `options`.
+    pub fn from_options(options: &Lz77Options) -> Self {
+        let mut flags = 0;
+        if options.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        if options.huffman {
+            flags |= FLAG_HUFFMAN;
+        }
+        if options.varint() {
+            flags |= FLAG_VARINT;
+        }
+        if options.frame_block_size().is_some() {
+            flags |= FLAG_BLOCKS;
+            if options.block_checksum {
+                flags |= FLAG_BLOCK_CHECKSUM;
+            }
+            if options.linked_blocks {
+                flags |= FLAG_LINKED_BLOCKS;
+            }
+            if options.block_index {
+                flags |= FLAG_BLOCK_INDEX;
+            }
+        }
+        Header {
+            window_bits: options.window_bits,
+            flags,
+        }
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+
+    pub fn as_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut res = [0u8; HEADER_SIZE];
+        res[..4].copy_from_slice(&MAGIC);
+        res[4] = VERSION;
+        res[5] = self.window_bits;
+        res[6] = self.flags;
+        res
+    }
+
+    pub fn parse(buf: &[u8; HEADER_SIZE]) -> Result<Self> {
+        if buf[..4] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        if buf[4] != VERSION {
+            return Err(Error::UnsupportedVersion(buf[4]));
+        }
+        // Every flag bit is assigned, new flags need a new version. The block flags only
+        // describe streams split into blocks, and an index needs independent blocks.
+        let flags = buf[6];
+        let block_flags = FLAG_BLOCK_CHECKSUM | FLAG_LINKED_BLOCKS | FLAG_BLOCK_INDEX;
+        let linked_index = FLAG_LINKED_BLOCKS | FLAG_BLOCK_INDEX;
+        if (flags & block_flags != 0 && flags & FLAG_BLOCKS == 0) ||
+           flags & linked_index == linked_index {
+            return Err(Error::InvalidFlags(flags));
+        }
+        let max_window_bits = if buf[6] & FLAG_VARINT != 0 {
+            MAX_WINDOW_BITS
+        } else {
+            MAX_CODEWORD_WINDOW_BITS
+        };
+        if buf[5] < MIN_WINDOW_BITS || buf[5] > max_window_bits {
+            return Err(Error::InvalidWindowSize(buf[5]));
+        }
+        Ok(Header {
+            window_bits: buf[5],
+            flags: buf[6],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = Header {
+            window_bits: 12,
+            flags: 0,
+        };
+        let bytes = header.as_bytes();
+        assert_eq!(&bytes[..4], b"LZ77");
+        let read = Header::parse(&bytes).expect("Header not valid");
+        assert_eq!(read, header);
+    }
+
+    #[test]
+    fn test_header_rejects_invalid() {
+        let header = Header {
+            window_bits: 12,
+            flags: 0,
+        };
+        let mut bad_magic = header.as_bytes();
+        bad_magic[0] = b'X';
+        assert_eq!(Header::parse(&bad_magic), Err(Error::InvalidMagic));
+
+        let mut bad_version = header.as_bytes();
+        bad_version[4] = VERSION + 1;
+        assert_eq!(Header::parse(&bad_version),
+                   Err(Error::UnsupportedVersion(VERSION + 1)));
+
+        let mut bad_window = header.as_bytes();
+        bad_window[5] = 16;
+        assert_eq!(Header::parse(&bad_window), Err(Error::InvalidWindowSize(16)));
+        bad_window[6] = FLAG_VARINT;
+        assert!(Header::parse(&bad_window).is_ok());
+        bad_window[5] = MAX_WINDOW_BITS + 1;
+        assert_eq!(Header::parse(&bad_window),
+                   Err(Error::InvalidWindowSize(MAX_WINDOW_BITS + 1)));
+
+        let mut bad_flags = header.as_bytes();
+        for &flags in &[FLAG_BLOCK_CHECKSUM,
+                        FLAG_LINKED_BLOCKS,
+                        FLAG_BLOCK_INDEX | FLAG_CHECKSUM,
+                        FLAG_BLOCKS | FLAG_LINKED_BLOCKS | FLAG_BLOCK_INDEX] {
+            bad_flags[6] = flags;
+            assert_eq!(Header::parse(&bad_flags), Err(Error::InvalidFlags(flags)));
+        }
+        bad_flags[6] = !FLAG_LINKED_BLOCKS;
+        assert!(Header::parse(&bad_flags).is_ok());
+    }
+}
@@ -0,0 +1,470 @@
+use std::cmp;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use errors::{Error, Result};
+
+/// Symbols of the literal/length alphabet below this are literal bytes.
+const END_OF_BLOCK: usize = 256;
+const LENGTH_BASE: usize = END_OF_BLOCK + 1;
+/// Number of codes needed to cover every `u32` value, see `value_code`.
+const VALUE_CODES: usize = 64;
+const LITLEN_SYMBOLS: usize = LENGTH_BASE + VALUE_CODES;
+const DIST_SYMBOLS: usize = VALUE_CODES;
+const MAX_CODE_LEN: u8 = 15;
+
+/// Symbols the encoder collects before it writes a block.
+pub const BLOCK_SYMBOLS: usize = 1 << 15;
+/// Largest block the decoder accepts, comfortably above what `BLOCK_SYMBOLS` can produce.
+pub const MAX_BLOCK_SIZE: usize = 1 << 20;
+
+/// Returns the largest size of the blocks, including the empty one ending the stream, that
+/// `len` bytes of input can be encoded to.
+pub fn compress_bound(len: usize,
+                      min_match_len: usize,
+                      window_bits: u8,
+                      max_match_len: usize)
+                      -> usize {
+    // A match has two codes and the extra bits of its length and distance, which have fewer
+    // bits than the largest values.
+    let extra_bits = window_bits as usize + (64 - (max_match_len as u64).leading_zeros() as usize);
+    let match_bits = (2 * MAX_CODE_LEN as usize + extra_bits).div_ceil(cmp::max(min_match_len, 1));
+    let bits = len * cmp::max(MAX_CODE_LEN as usize, match_bits);
+    let tables = (9 + 4 * LITLEN_SYMBOLS + 7 + 4 * DIST_SYMBOLS).div_ceil(8);
+    let blocks = len / BLOCK_SYMBOLS + 1;
+    bits.div_ceil(8) + blocks * (4 + tables + 2) + 4
+}
+
+/// A token of the entropy-coded format. Unlike codewords, matches are not followed by a literal
+/// and literals are not grouped into runs.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Symbol {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+/// Splits `value` into a code and extra bits, as DEFLATE does for distances: values below 4
+/// are codes of their own, every further power of two is split into two codes that each carry
+/// the bits below the top two as extra bits.
+fn value_code(value: u32) -> (usize, u32, u32) {
+    if value < 4 {
+        return (value as usize, 0, 0);
+    }
+    let top_bit = 31 - value.leading_zeros();
+    let extra_bits = top_bit - 1;
+    let code = 2 * top_bit + ((value >> extra_bits) & 1);
+    (code as usize, extra_bits, value & ((1 << extra_bits) - 1))
+}
+
+fn code_extra_bits(code: usize) -> u32 {
+    if code < 4 { 0 } else { code as u32 / 2 - 1 }
+}
+
+fn code_value(code: usize, extra: u32) -> u32 {
+    if code < 4 {
+        return code as u32;
+    }
+    ((2 | (code as u32 & 1)) << code_extra_bits(code)) | extra
+}
+
+/// Computes Huffman code lengths for `freqs`, halving the frequencies until no code is longer
+/// than `MAX_CODE_LEN`. Symbols with a frequency of zero get no code.
+fn code_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    loop {
+        let lengths = unlimited_code_lengths(&freqs);
+        if lengths.iter().all(|&len| len <= MAX_CODE_LEN) {
+            return lengths;
+        }
+        for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+            *freq = (*freq >> 1) | 1;
+        }
+    }
+}
+
+fn unlimited_code_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut heap = BinaryHeap::new();
+    let mut parent = Vec::new();
+    let mut leaves = Vec::new();
+    for (symbol, &freq) in freqs.iter().enumerate().filter(|&(_, &freq)| freq > 0) {
+        heap.push(Reverse((freq as u64, parent.len())));
+        leaves.push((symbol, parent.len()));
+        parent.push(usize::MAX);
+    }
+    if leaves.len() == 1 {
+        lengths[leaves[0].0] = 1;
+    }
+    while heap.len() > 1 {
+        let Reverse((freq_a, a)) = heap.pop().unwrap();
+        let Reverse((freq_b, b)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[a] = node;
+        parent[b] = node;
+        heap.push(Reverse((freq_a + freq_b, node)));
+    }
+    for &(symbol, leaf) in &leaves {
+        let mut node = leaf;
+        let mut depth = 0u8;
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            depth = depth.saturating_add(1);
+        }
+        if depth > 0 {
+            lengths[symbol] = depth;
+        }
+    }
+    lengths
+}
+
+/// Assigns canonical codes: shorter codes first, codes of equal length in symbol order.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut count = [0u16; MAX_CODE_LEN as usize + 1];
+    for &len in lengths.iter().filter(|&&len| len > 0) {
+        count[len as usize] += 1;
+    }
+    let mut next = [0u16; MAX_CODE_LEN as usize + 1];
+    let mut code = 0u16;
+    for len in 1..next.len() {
+        code = (code + count[len - 1]) << 1;
+        next[len] = code;
+    }
+    lengths.iter()
+        .map(|&len| if len == 0 {
+            0
+        } else {
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            out: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Appends the `n` low bits of `value`, most significant bit first.
+    fn write(&mut self, value: u32, n: u32) {
+        if n == 0 {
+            return;
+        }
+        self.acc = (self.acc << n) | (value as u64 & ((1 << n) - 1));
+        self.bits += n;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.out.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1 << self.bits) - 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push((self.acc << (8 - self.bits)) as u8);
+        }
+        self.out
+    }
+}
+
+struct BitReader<D> {
+    data: D,
+    pos: usize,
+}
+
+impl<D> BitReader<D>
+    where D: AsRef<[u8]>
+{
+    fn read_bit(&mut self) -> Result<u32> {
+        let byte = *self.data
+            .as_ref()
+            .get(self.pos / 8)
+            .ok_or(Error::CorruptBlock("unexpected end of block"))?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit as u32)
+    }
+
+    fn read(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()?;
+        }
+        Ok(value)
+    }
+}
+
+/// Decoding table of a canonical code, holding the number of codes of every length and the
+/// symbols in code order.
+struct Table {
+    count: [u16; MAX_CODE_LEN as usize + 1],
+    symbols: Vec<u16>,
+}
+
+impl Table {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut count = [0u16; MAX_CODE_LEN as usize + 1];
+        for &len in lengths.iter().filter(|&&len| len > 0) {
+            count[len as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for &n in &count[1..] {
+            left = (left << 1) - n as i32;
+            if left < 0 {
+                return Err(Error::CorruptBlock("over-subscribed code lengths"));
+            }
+        }
+        let mut symbols = Vec::new();
+        for len in 1..(MAX_CODE_LEN + 1) {
+            symbols.extend((0..lengths.len()).filter(|&s| lengths[s] == len).map(|s| s as u16));
+        }
+        Ok(Table { count, symbols })
+    }
+
+    fn decode<D>(&self, bits: &mut BitReader<D>) -> Result<usize>
+        where D: AsRef<[u8]>
+    {
+        let mut code: u32 = 0;
+        let mut first: u32 = 0;
+        let mut index: u32 = 0;
+        for &count in &self.count[1..] {
+            code |= bits.read_bit()?;
+            let count = count as u32;
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::CorruptBlock("invalid code"))
+    }
+}
+
+fn write_lengths(bits: &mut BitWriter, lengths: &[u8], count_bits: u32) {
+    let used = lengths.iter().rposition(|&len| len > 0).map_or(0, |last| last + 1);
+    bits.write(used as u32, count_bits);
+    for &len in &lengths[..used] {
+        bits.write(len as u32, 4);
+    }
+}
+
+fn read_lengths<D>(bits: &mut BitReader<D>, symbols: usize, count_bits: u32) -> Result<Table>
+    where D: AsRef<[u8]>
+{
+    let used = bits.read(count_bits)? as usize;
+    if used > symbols {
+        return Err(Error::CorruptBlock("too many code lengths"));
+    }
+    let mut lengths = vec![0u8; symbols];
+    for len in &mut lengths[..used] {
+        *len = bits.read(4)? as u8;
+    }
+    Table::new(&lengths)
+}
+
+/// Collects symbols and writes them as entropy-coded blocks.
+///
+/// A block is the size of its payload as a big-endian `u32`, followed by the payload: the code
+/// lengths of the literal/length and distance alphabets, the coded symbols and an end of block
+/// symbol, padded to a full byte. A block of size zero ends the stream.
+pub struct HuffmanWriter {
+    symbols: Vec<Symbol>,
+    litlen_freqs: Vec<u32>,
+    dist_freqs: Vec<u32>,
+}
+
+impl HuffmanWriter {
+    pub fn new() -> Self {
+        HuffmanWriter {
+            symbols: Vec::with_capacity(BLOCK_SYMBOLS),
+            litlen_freqs: vec![0; LITLEN_SYMBOLS],
+            dist_freqs: vec![0; DIST_SYMBOLS],
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.symbols.len() >= BLOCK_SYMBOLS
+    }
+
+    pub fn push(&mut self, symbol: Symbol) {
+        match symbol {
+            Symbol::Literal(byte) => self.litlen_freqs[byte as usize] += 1,
+            Symbol::Match { length, distance } => {
+                self.litlen_freqs[LENGTH_BASE + value_code(length as u32 - 1).0] += 1;
+                self.dist_freqs[value_code(distance as u32 - 1).0] += 1;
+            }
+        }
+        self.symbols.push(symbol);
+    }
+
+    /// Writes the collected symbols as a block, if there are any.
+    pub fn write_block<W>(&mut self, out: &mut W) -> io::Result<()>
+        where W: io::Write
+    {
+        if self.symbols.is_empty() {
+            return Ok(());
+        }
+        self.litlen_freqs[END_OF_BLOCK] += 1;
+        let litlen_lengths = code_lengths(&self.litlen_freqs);
+        let dist_lengths = code_lengths(&self.dist_freqs);
+        let litlen_codes = canonical_codes(&litlen_lengths);
+        let dist_codes = canonical_codes(&dist_lengths);
+
+        let mut bits = BitWriter::new();
+        write_lengths(&mut bits, &litlen_lengths, 9);
+        write_lengths(&mut bits, &dist_lengths, 7);
+        {
+            let write_symbol = |bits: &mut BitWriter, symbol: usize| {
+                bits.write(litlen_codes[symbol] as u32, litlen_lengths[symbol] as u32);
+            };
+            for symbol in &self.symbols {
+                match *symbol {
+                    Symbol::Literal(byte) => write_symbol(&mut bits, byte as usize),
+                    Symbol::Match { length, distance } => {
+                        let (code, extra_bits, extra) = value_code(length as u32 - 1);
+                        write_symbol(&mut bits, LENGTH_BASE + code);
+                        bits.write(extra, extra_bits);
+                        let (code, extra_bits, extra) = value_code(distance as u32 - 1);
+                        bits.write(dist_codes[code] as u32, dist_lengths[code] as u32);
+                        bits.write(extra, extra_bits);
+                    }
+                }
+            }
+            write_symbol(&mut bits, END_OF_BLOCK);
+        }
+        let payload = bits.finish();
+        out.write_all(&(payload.len() as u32).to_be_bytes())?;
+        out.write_all(&payload)?;
+
+        self.symbols.clear();
+        for freq in self.litlen_freqs.iter_mut().chain(self.dist_freqs.iter_mut()) {
+            *freq = 0;
+        }
+        Ok(())
+    }
+
+    /// Writes the collected symbols followed by the empty block that ends the stream.
+    pub fn write_end<W>(&mut self, out: &mut W) -> io::Result<()>
+        where W: io::Write
+    {
+        self.write_block(out)?;
+        out.write_all(&[0u8; 4])
+    }
+}
+
+/// Decodes the symbols of a single block payload.
+pub struct BlockDecoder<D> {
+    bits: BitReader<D>,
+    litlen: Table,
+    dist: Table,
+}
+
+impl<D> BlockDecoder<D>
+    where D: AsRef<[u8]>
+{
+    pub fn new(payload: D) -> Result<Self> {
+        let mut bits = BitReader {
+            data: payload,
+            pos: 0,
+        };
+        let litlen = read_lengths(&mut bits, LITLEN_SYMBOLS, 9)?;
+        let dist = read_lengths(&mut bits, DIST_SYMBOLS, 7)?;
+        Ok(BlockDecoder { bits, litlen, dist })
+    }
+
+    fn read_value(&mut self, code: usize) -> Result<usize> {
+        let extra = self.bits.read(code_extra_bits(code))?;
+        Ok((code_value(code, extra) as usize).saturating_add(1))
+    }
+
+    /// Returns the next symbol, or `None` at the end of the block.
+    pub fn next_symbol(&mut self) -> Result<Option<Symbol>> {
+        let symbol = self.litlen.decode(&mut self.bits)?;
+        if symbol < END_OF_BLOCK {
+            return Ok(Some(Symbol::Literal(symbol as u8)));
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(None);
+        }
+        let length = self.read_value(symbol - LENGTH_BASE)?;
+        let code = self.dist.decode(&mut self.bits)?;
+        let distance = self.read_value(code)?;
+        Ok(Some(Symbol::Match { length, distance }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_code() {
+        for &value in &[0u32, 1, 3, 4, 5, 6, 7, 8, 100, 4095, 32767, 1 << 20, u32::MAX] {
+            let (code, extra_bits, extra) = value_code(value);
+            assert!(code < VALUE_CODES);
+            assert_eq!(code_extra_bits(code), extra_bits);
+            assert_eq!(code_value(code, extra), value);
+        }
+    }
+
+    #[test]
+    fn test_code_lengths_limited() {
+        // Fibonacci frequencies produce the deepest possible tree.
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 40 {
+            let n = freqs.len();
+            freqs.push(freqs[n - 1] + freqs[n - 2]);
+        }
+        let lengths = code_lengths(&freqs);
+        assert!(lengths.iter().all(|&len| len > 0 && len <= MAX_CODE_LEN));
+        assert!(Table::new(&lengths).is_ok());
+        assert_eq!(code_lengths(&[0, 5, 0]), vec![0, 1, 0]);
+        assert_eq!(code_lengths(&[0, 0]), vec![0, 0]);
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let symbols = [Symbol::Literal(b'a'),
+                       Symbol::Literal(b'b'),
+                       Symbol::Match { length: 2, distance: 2 },
+                       Symbol::Literal(b'c'),
+                       Symbol::Match { length: 300, distance: 1 },
+                       Symbol::Match { length: 7, distance: 30000 }];
+        let mut writer = HuffmanWriter::new();
+        for &symbol in &symbols {
+            writer.push(symbol);
+        }
+        let mut out = Vec::new();
+        writer.write_end(&mut out).unwrap();
+        let size = u32::from_be_bytes([out[0], out[1], out[2], out[3]]) as usize;
+        assert_eq!(&out[(4 + size)..], &[0, 0, 0, 0]);
+
+        let mut decoder = BlockDecoder::new(&out[4..(4 + size)]).unwrap();
+        for &symbol in &symbols {
+            assert_eq!(decoder.next_symbol(), Ok(Some(symbol)));
+        }
+        assert_eq!(decoder.next_symbol(), Ok(None));
+    }
+
+    #[test]
+    fn test_corrupt_block() {
+        assert_eq!(BlockDecoder::new(&[][..]).err(),
+                   Some(Error::CorruptBlock("unexpected end of block")));
+        // Three literal/length codes of length one.
+        let payload = [0x01, 0x88, 0x88, 0x00];
+        assert_eq!(BlockDecoder::new(&payload[..]).err(),
+                   Some(Error::CorruptBlock("over-subscribed code lengths")));
+    }
+}
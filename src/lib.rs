@@ -1,36 +1,310 @@
-#![recursion_limit = "1024"]
-#[macro_use]
-extern crate error_chain;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
 mod buffer;
+mod checksum;
 mod codeword;
+mod header;
+mod huffman;
+mod frame;
+mod parser;
+mod optimal;
+mod token;
+mod oneshot;
+mod pool;
+#[cfg(test)]
+mod test_util;
 pub mod errors;
+pub mod dict;
 pub mod searcher;
 pub mod encoder;
+pub mod compressor;
 pub mod decoder;
+pub mod decompressor;
+pub mod parallel_encoder;
+pub mod parallel_decoder;
+pub mod seekable_decoder;
+#[cfg(feature = "tokio")]
+pub mod async_encoder;
+#[cfg(feature = "tokio")]
+pub mod async_decoder;
 
 pub use encoder::Lz77Encoder;
+pub use compressor::{Compressor, FlushMode};
 pub use decoder::Lz77Decoder;
-pub use searcher::LinearSearcher;
+pub use decompressor::{Decompressor, Status};
+pub use parallel_encoder::ParallelLz77Encoder;
+pub use parallel_decoder::ParallelLz77Decoder;
+pub use seekable_decoder::SeekableLz77Decoder;
+#[cfg(feature = "tokio")]
+pub use async_encoder::AsyncLz77Encoder;
+#[cfg(feature = "tokio")]
+pub use async_decoder::AsyncLz77Decoder;
+pub use oneshot::{compress, compress_into, compress_bound, decompress, decompress_into};
+pub use searcher::{LinearSearcher, HashChainSearcher, BinaryTreeSearcher, SuffixArraySearcher,
+                   AnySearcher, SearcherKind};
+
+use codeword::CodeWordFormat;
+use errors::Error;
+
+/// Smallest window for which every literal run and match still fits into the window.
+pub const MIN_WINDOW_BITS: u8 = 9;
+/// Largest window supported with variable-length codewords.
+pub const MAX_WINDOW_BITS: u8 = 24;
+/// Largest window that leaves at least one bit for the length field of a 16-bit codeword.
+pub const MAX_CODEWORD_WINDOW_BITS: u8 = 15;
+/// Longest match supported with variable-length codewords.
+pub const MAX_MATCH_LEN: usize = 65535;
+/// Longest match used for windows too large for 16-bit codewords when `max_match_len` is unset.
+pub const DEFAULT_MAX_MATCH_LEN: usize = 258;
+/// Shortest match used with Huffman coding when `min_match_len` is unset.
+pub const DEFAULT_HUFFMAN_MIN_MATCH_LEN: usize = 3;
+/// Largest number of positions lazy matching looks ahead.
+pub const MAX_LAZY: u8 = 2;
+/// Largest `block_size`, which keeps the sizes in a block header within 32 bits.
+pub const MAX_BLOCK_SIZE: usize = 1 << 30;
+/// Compression level used by `Lz77Options::default`.
+pub const DEFAULT_LEVEL: u8 = 6;
 
+#[derive(Clone, Debug)]
 pub struct Lz77Options {
-    pub window_size: u8,
+    /// Size of the window in bits. Windows larger than `MAX_CODEWORD_WINDOW_BITS` are written
+    /// with variable-length codewords.
+    pub window_bits: u8,
+    /// Omits the stream header and end marker, producing the headerless format. A raw stream
+    /// can only be decoded with the same `window_bits` it was encoded with.
+    pub raw: bool,
+    /// Appends a CRC-32 of the uncompressed content to the stream, which the decoder verifies
+    /// once it reaches the end marker. Ignored in raw mode.
+    pub checksum: bool,
+    /// Writes the tokens as blocks entropy-coded with per-block Huffman tables instead of
+    /// fixed-size codewords. Ignored in raw mode.
+    pub huffman: bool,
+    /// Number of following positions the encoder checks for a strictly longer match before
+    /// emitting the one found at the current position. `0` disables lazy matching.
+    pub lazy: u8,
+    /// Chooses the tokens for blocks of up to 4096 bytes by their encoded size instead of
+    /// taking the longest match at each position. Slower, as every position is searched, and
+    /// best combined with a `BinaryTree` searcher, which reports several candidates per
+    /// position. `lazy` is ignored.
+    pub optimal: bool,
+    /// Searcher picked by `AnySearcher`. Encoders with a fixed searcher type ignore this.
+    pub searcher: SearcherKind,
+    /// Maximum number of candidates a hash chain searcher compares per position.
+    pub max_chain: usize,
+    /// Shortest match the searchers report and the encoder emits, shorter ones are encoded as
+    /// literals. `None` uses the shortest match whose codeword and following literal are no
+    /// larger than the bytes it replaces at any distance, or 3 with Huffman coding.
+    pub min_match_len: Option<usize>,
+    /// Matches at least this long are taken right away, without looking at further candidates
+    /// or checking the following positions for lazy matching.
+    pub good_match_len: usize,
+    /// Longest match the encoder emits. `None` uses the longest match a 16-bit codeword holds
+    /// next to the distance, or `DEFAULT_MAX_MATCH_LEN` for larger windows. Longer matches are
+    /// written with variable-length codewords.
+    pub max_match_len: Option<usize>,
+    /// Largest number of bytes a decoder produces before failing with
+    /// `Error::OutputLimitExceeded`. `None` disables the limit.
+    pub max_output_size: Option<u64>,
+    /// Largest ratio of decoded bytes to compressed bytes read so far, including the header,
+    /// before a decoder fails with `Error::OutputLimitExceeded`. `None` disables the limit.
+    pub max_expansion_ratio: Option<u64>,
+    /// Splits the content into blocks of up to this many bytes, each written with its
+    /// compressed and uncompressed size, so that a corrupt block does not affect the others.
+    /// Flushing the encoder ends the current block early. `None` writes a single token stream.
+    /// Ignored in raw mode.
+    pub block_size: Option<usize>,
+    /// Writes a CRC-32 of the content of every block into its header, which the decoder
+    /// verifies at the end of the block. Ignored without `block_size`.
+    pub block_checksum: bool,
+    /// Lets matches reach back into the previous blocks. Compresses better, but the blocks can
+    /// only be decoded in order. Without it, every block starts with an empty window and can
+    /// be decoded on its own. Ignored without `block_size`.
+    pub linked_blocks: bool,
+    /// Appends an index of the offsets of all blocks, which lets a `SeekableLz77Decoder`
+    /// decode only the blocks holding the content it reads. Can not be combined with
+    /// `linked_blocks`. Ignored without `block_size`.
+    pub block_index: bool,
+}
+
+impl Default for Lz77Options {
+    fn default() -> Self {
+        Lz77Options::with_level(DEFAULT_LEVEL)
+    }
 }
 
+impl Lz77Options {
+    /// Returns the options for a compression level between 1 (fastest) and 9 (smallest
+    /// output). Levels outside that range are clamped.
+    pub fn with_level(level: u8) -> Self {
+        let (searcher, max_chain, lazy, good_match_len) = match level {
+            0 | 1 => (SearcherKind::HashChain, 4, 0, 8),
+            2 => (SearcherKind::HashChain, 8, 0, 16),
+            3 => (SearcherKind::HashChain, 16, 0, 32),
+            4 => (SearcherKind::HashChain, 32, 1, 16),
+            5 => (SearcherKind::HashChain, 64, 1, 32),
+            6 => (SearcherKind::HashChain, 128, 1, 64),
+            7 => (SearcherKind::HashChain, 256, 1, 128),
+            8 => (SearcherKind::HashChain, 1024, 1, 256),
+            _ => (SearcherKind::BinaryTree, 0, 1, usize::MAX),
+        };
+        Lz77Options {
+            window_bits: 12,
+            raw: false,
+            checksum: true,
+            huffman: false,
+            lazy,
+            optimal: false,
+            searcher,
+            max_chain,
+            min_match_len: None,
+            good_match_len,
+            max_match_len: None,
+            max_output_size: None,
+            max_expansion_ratio: None,
+            block_size: None,
+            block_checksum: false,
+            linked_blocks: false,
+            block_index: false,
+        }
+    }
+
+    /// Checks that the options describe a usable stream format.
+    pub fn validate(&self) -> errors::Result<()> {
+        if self.window_bits < MIN_WINDOW_BITS || self.window_bits > MAX_WINDOW_BITS {
+            return Err(Error::InvalidOptions("window_bits must be between 9 and 24 bits"));
+        }
+        if let Some(len) = self.max_match_len {
+            // A match and the literal following it have to fit into the window.
+            if len == 0 || len > MAX_MATCH_LEN || len > (1 << self.window_bits) - 2 {
+                return Err(Error::InvalidOptions("max_match_len must be between 1 and 65535 \
+                                                  and smaller than the window"));
+            }
+        }
+        if self.min_match_len == Some(0) {
+            return Err(Error::InvalidOptions("min_match_len must be at least 1"));
+        }
+        if self.lazy > MAX_LAZY {
+            return Err(Error::InvalidOptions("lazy must be at most 2"));
+        }
+        if let Some(size) = self.block_size {
+            if size == 0 || size > MAX_BLOCK_SIZE {
+                return Err(Error::InvalidOptions("block_size must be between 1 byte and 1 GiB"));
+            }
+        }
+        if self.block_index && self.linked_blocks {
+            return Err(Error::InvalidOptions("block_index needs independent blocks"));
+        }
+        Ok(())
+    }
+
+    /// Returns the longest match a 16-bit codeword holds with this window, zero if the window
+    /// leaves no bits for the length.
+    fn codeword_max_match_len(&self) -> usize {
+        if self.window_bits > MAX_CODEWORD_WINDOW_BITS {
+            0
+        } else {
+            (1 << (16 - self.window_bits)) - 1
+        }
+    }
+
+    /// Returns the longest match the encoder emits.
+    fn match_len_limit(&self) -> usize {
+        match self.max_match_len {
+            Some(len) => len,
+            None if self.window_bits > MAX_CODEWORD_WINDOW_BITS => DEFAULT_MAX_MATCH_LEN,
+            None => self.codeword_max_match_len(),
+        }
+    }
+
+    /// Returns the shortest match the encoder emits.
+    fn match_len_floor(&self) -> usize {
+        if let Some(len) = self.min_match_len {
+            return len;
+        }
+        if self.huffman && !self.raw {
+            return DEFAULT_HUFFMAN_MIN_MATCH_LEN;
+        }
+        let format = CodeWordFormat::new(self.window_bits, self.varint());
+        let max_distance = (1 << self.window_bits as usize) - 1;
+        let mut len = 1;
+        while format.size(max_distance, len) + 1 > len {
+            len += 1;
+        }
+        len
+    }
+
+    /// Returns the size of the blocks the content is split into, `None` if it is written as a
+    /// single token stream.
+    fn frame_block_size(&self) -> Option<usize> {
+        if self.raw {
+            None
+        } else {
+            self.block_size
+        }
+    }
+
+    /// Returns whether the window or match length need variable-length codewords.
+    fn varint(&self) -> bool {
+        self.match_len_limit() > self.codeword_max_match_len()
+    }
+
+    /// Checks that a decoder may have produced `decoded` bytes after reading `consumed`
+    /// compressed bytes.
+    fn check_output_limit(&self, decoded: u64, consumed: u64) -> errors::Result<()> {
+        if let Some(limit) = self.max_output_size {
+            if decoded > limit {
+                return Err(Error::OutputLimitExceeded { limit });
+            }
+        }
+        if let Some(ratio) = self.max_expansion_ratio {
+            let limit = consumed.saturating_mul(ratio);
+            if decoded > limit {
+                return Err(Error::OutputLimitExceeded { limit });
+            }
+        }
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use encoder::Lz77Encoder;
     use decoder::Lz77Decoder;
-    use ::Lz77Options;
-    use std::io::{copy, Write, Cursor};
+    use ::{Lz77Options, MAX_BLOCK_SIZE};
+    use errors::Error;
+    use std::io;
+    use std::io::{copy, Write, Cursor, ErrorKind};
+    use searcher::{Searcher, AnySearcher, SearcherKind};
     use searcher::linear_search::LinearSearcher;
+    use searcher::hash_chain::HashChainSearcher;
+    use searcher::binary_tree::BinaryTreeSearcher;
+    use test_util::{sample_data, random_data};
+
+    fn round_trip<S>(data: &[u8], window_bits: u8, searcher: S) -> (Vec<u8>, Vec<u8>)
+        where S: Searcher + Default
+    {
+        let mut compressed = Vec::new();
+        {
+            let opts = Lz77Options { window_bits, ..Default::default() };
+            let mut encoder = Lz77Encoder::with_searcher(&mut compressed, opts, searcher);
+            encoder.write_all(data).expect("Encoding failed");
+            encoder.flush().expect("Flush failed");
+        }
+        let mut output = Vec::new();
+        {
+            let mut cursor = Cursor::new(&compressed);
+            let mut decoder = Lz77Decoder::new(&mut cursor, Lz77Options::default());
+            copy(&mut decoder, &mut output).expect("Decoding failed");
+        }
+        (compressed, output)
+    }
 
     #[test]
     fn test_write() {
         let mut buf = Vec::new();
         {
-            let opts = Lz77Options { window_size: 12 };
+            let opts = Lz77Options { window_bits: 12, ..Default::default() };
             let mut encoder = Lz77Encoder::<_, LinearSearcher>::new(&mut buf, opts);
             assert!(write!(encoder,
                            "aaabcabcaaaa abc abc abc aaacccdddbla b,asfdsafsafs fsadfsdfasf")
@@ -38,7 +312,7 @@ mod tests {
             assert!(encoder.flush().is_ok());
         }
         {
-            let opts = Lz77Options { window_size: 12 };
+            let opts = Lz77Options { window_bits: 12, ..Default::default() };
             let mut cursor = Cursor::new(buf);
             let mut decoder = Lz77Decoder::new(&mut cursor, opts);
             let mut output = Vec::new();
@@ -50,4 +324,615 @@ mod tests {
             println!("String::from_utf8(output) = {:#?}", string);
         }
     }
+
+    #[test]
+    fn test_searchers_match_linear() {
+        let data = sample_data(20000);
+        for &window_bits in &[9u8, 10, 12] {
+            let (linear, linear_out) = round_trip(&data, window_bits, LinearSearcher::default());
+            let (chain, chain_out) = round_trip(&data, window_bits, HashChainSearcher::default());
+            let (tree, tree_out) = round_trip(&data, window_bits, BinaryTreeSearcher::default());
+            assert_eq!(linear_out, data);
+            assert_eq!(chain_out, data);
+            assert_eq!(tree_out, data);
+            assert!(chain.len() < data.len());
+            // Both find the longest match, which is all that matters for fixed-size codewords.
+            assert_eq!(tree.len(), linear.len());
+        }
+    }
+
+    #[test]
+    fn test_header_configures_decoder() {
+        let data = sample_data(5000);
+        let mut compressed = Vec::new();
+        {
+            let opts = Lz77Options { window_bits: 10, ..Default::default() };
+            let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(&mut compressed, opts);
+            encoder.write_all(&data).expect("Encoding failed");
+        }
+        assert_eq!(&compressed[..4], b"LZ77");
+
+        let opts = Lz77Options { window_bits: 14, ..Default::default() };
+        let mut decoder = Lz77Decoder::new(Cursor::new(&compressed), opts);
+        let mut output = Vec::new();
+        copy(&mut decoder, &mut output).expect("Decoding failed");
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_raw_mode() {
+        let data = sample_data(5000);
+        let mut compressed = Vec::new();
+        {
+            let opts = Lz77Options { window_bits: 10, raw: true, ..Default::default() };
+            let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(&mut compressed, opts);
+            encoder.write_all(&data).expect("Encoding failed");
+        }
+        assert!(&compressed[..4] != b"LZ77");
+
+        let opts = Lz77Options { window_bits: 10, raw: true, ..Default::default() };
+        let mut decoder = Lz77Decoder::new(Cursor::new(&compressed), opts);
+        let mut output = Vec::new();
+        copy(&mut decoder, &mut output).expect("Decoding failed");
+        assert_eq!(output, data);
+
+        let mut decoder = Lz77Decoder::new(Cursor::new(&compressed), Lz77Options::default());
+        let err = copy(&mut decoder, &mut Vec::new()).expect_err("Raw stream accepted as framed");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let mut compressed = Vec::new();
+        {
+            let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(&mut compressed,
+                                                                       Lz77Options::default());
+            encoder.write_all(&sample_data(1000)).expect("Encoding failed");
+        }
+        let len = compressed.len();
+        compressed.truncate(len - 2);
+        let mut decoder = Lz77Decoder::new(Cursor::new(&compressed), Lz77Options::default());
+        let err = copy(&mut decoder, &mut Vec::new()).expect_err("Truncated stream accepted");
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    fn compress_default(data: &[u8], opts: Lz77Options) -> Vec<u8> {
+        let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(Vec::new(), opts);
+        encoder.write_all(data).expect("Encoding failed");
+        encoder.finish().expect("Finishing failed")
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let data = sample_data(2000);
+        let compressed = compress_default(&data, Lz77Options::default());
+
+        let mut output = Vec::new();
+        let mut decoder = Lz77Decoder::new(Cursor::new(&compressed), Lz77Options::default());
+        copy(&mut decoder, &mut output).expect("Decoding failed");
+        assert_eq!(output, data);
+
+        // The first token is a literal run, corrupt its first literal.
+        let mut corrupt = compressed.clone();
+        corrupt[9] ^= 0x01;
+        let mut decoder = Lz77Decoder::new(Cursor::new(&corrupt), Lz77Options::default());
+        let err = copy(&mut decoder, &mut Vec::new()).expect_err("Corruption not detected");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(&Error::ChecksumMismatch { .. }) => {}
+            other => panic!("Expected checksum mismatch, got {:?}", other),
+        }
+
+        let mut corrupt = compressed.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0x80;
+        let mut decoder = Lz77Decoder::new(Cursor::new(&corrupt), Lz77Options::default());
+        let err = copy(&mut decoder, &mut Vec::new()).expect_err("Corruption not detected");
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(&Error::ChecksumMismatch { .. }) => {}
+            other => panic!("Expected checksum mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_without_checksum() {
+        let data = sample_data(2000);
+        let with = compress_default(&data, Lz77Options::default());
+        let without = compress_default(&data,
+                                       Lz77Options { checksum: false, ..Default::default() });
+        assert_eq!(without.len() + 4, with.len());
+
+        let mut output = Vec::new();
+        let mut decoder = Lz77Decoder::new(Cursor::new(&without), Lz77Options::default());
+        copy(&mut decoder, &mut output).expect("Decoding failed");
+        assert_eq!(output, data);
+    }
+
+    struct LimitedWriter {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() + buf.len() > self.limit {
+                return Err(io::Error::new(ErrorKind::WriteZero, "Writer is full"));
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_finish() {
+        let data = sample_data(3000);
+        let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(Vec::new(),
+                                                                   Lz77Options::default());
+        encoder.write_all(&data).expect("Encoding failed");
+        assert_eq!(encoder.get_ref().len(), encoder.get_mut().len());
+        let compressed = encoder.finish().expect("Finishing failed");
+        assert_eq!(compressed, compress_default(&data, Lz77Options::default()));
+
+        let mut output = Vec::new();
+        let mut decoder = Lz77Decoder::new(Cursor::new(&compressed), Lz77Options::default());
+        copy(&mut decoder, &mut output).expect("Decoding failed");
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_finish_reports_errors() {
+        let data = sample_data(3000);
+        let compressed = compress_default(&data, Lz77Options::default());
+        let writer = LimitedWriter {
+            written: Vec::new(),
+            limit: compressed.len() - 1,
+        };
+        let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(writer,
+                                                                   Lz77Options::default());
+        encoder.write_all(&data).expect("Encoding failed");
+        assert!(encoder.finish().is_err());
+    }
+
+    #[test]
+    fn test_into_inner() {
+        let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(Vec::new(),
+                                                                   Lz77Options::default());
+        encoder.write_all(b"abc").expect("Encoding failed");
+        let inner = encoder.into_inner();
+        assert_eq!(&inner[..], b"LZ77\x01\x0c\x01");
+    }
+
+    #[test]
+    fn test_validate_options() {
+        assert!(Lz77Options::default().validate().is_ok());
+        assert!(Lz77Options { window_bits: 8, ..Default::default() }.validate().is_err());
+        assert!(Lz77Options { window_bits: 24, ..Default::default() }.validate().is_ok());
+        assert!(Lz77Options { window_bits: 25, ..Default::default() }.validate().is_err());
+        assert!(Lz77Options { max_match_len: Some(0), ..Default::default() }.validate().is_err());
+        assert!(Lz77Options { max_match_len: Some(4094), ..Default::default() }
+            .validate()
+            .is_ok());
+        assert!(Lz77Options { max_match_len: Some(4095), ..Default::default() }
+            .validate()
+            .is_err());
+        assert!(Lz77Options { lazy: 3, ..Default::default() }.validate().is_err());
+        assert!(Lz77Options { block_size: Some(0), ..Default::default() }.validate().is_err());
+        assert!(Lz77Options { block_size: Some(MAX_BLOCK_SIZE + 1), ..Default::default() }
+            .validate()
+            .is_err());
+        assert!(Lz77Options { block_index: true, linked_blocks: true, ..Default::default() }
+            .validate()
+            .is_err());
+    }
+
+    fn decompress_default(compressed: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut decoder = Lz77Decoder::new(Cursor::new(compressed), Lz77Options::default());
+        copy(&mut decoder, &mut output).expect("Decoding failed");
+        output
+    }
+
+    #[test]
+    fn test_lazy_matching_round_trip() {
+        let data = sample_data(20000);
+        for lazy in 1..3 {
+            let opts = Lz77Options { lazy, ..Default::default() };
+            let compressed = compress_default(&data, opts);
+            assert_eq!(decompress_default(&compressed), data);
+
+            let opts = Lz77Options { lazy, ..Default::default() };
+            let mut encoder = Lz77Encoder::<_, LinearSearcher>::new(Vec::new(), opts);
+            encoder.write_all(&data[..3000]).expect("Encoding failed");
+            let compressed = encoder.finish().expect("Finishing failed");
+            assert_eq!(decompress_default(&compressed), &data[..3000]);
+        }
+    }
+
+    #[test]
+    fn test_lazy_matching_prefers_longer_match() {
+        // Greedy matching takes "xab" after the dot, which hides the longer "abcdefghij"
+        // starting one byte later.
+        let data = b"abcdefghij-xab.xabcdefghij";
+        let greedy = compress_default(data, Lz77Options { lazy: 0, ..Default::default() });
+        let opts = Lz77Options { lazy: 1, ..Default::default() };
+        let lazy = compress_default(data, opts);
+        assert!(lazy.len() < greedy.len());
+        assert_eq!(decompress_default(&lazy), &data[..]);
+    }
+
+    #[test]
+    fn test_levels() {
+        let data = sample_data(10000);
+        let mut sizes = Vec::new();
+        for level in 1..10 {
+            let opts = Lz77Options::with_level(level);
+            let mut encoder = Lz77Encoder::<_, AnySearcher>::new(Vec::new(), opts);
+            encoder.write_all(&data).expect("Encoding failed");
+            let compressed = encoder.finish().expect("Finishing failed");
+            assert_eq!(decompress_default(&compressed), data);
+            sizes.push(compressed.len());
+        }
+        assert!(sizes[8] <= sizes[0]);
+        assert!(sizes[5] <= sizes[0]);
+    }
+
+    #[test]
+    fn test_huffman_round_trip() {
+        let data = sample_data(100000);
+        for &window_bits in &[9u8, 12, 15] {
+            let opts = Lz77Options { window_bits, huffman: true, ..Default::default() };
+            let mut encoder = Lz77Encoder::<_, AnySearcher>::new(Vec::new(), opts);
+            for chunk in data.chunks(30000) {
+                encoder.write_all(chunk).expect("Encoding failed");
+                encoder.flush().expect("Flush failed");
+            }
+            let compressed = encoder.finish().expect("Finishing failed");
+            assert_eq!(decompress_default(&compressed), data);
+        }
+    }
+
+    #[test]
+    fn test_huffman_smaller() {
+        let data = sample_data(50000);
+        let plain = compress_default(&data, Lz77Options::default());
+        let huffman = compress_default(&data, Lz77Options { huffman: true, ..Default::default() });
+        assert!(huffman.len() < plain.len());
+        assert_eq!(decompress_default(&huffman), data);
+    }
+
+    /// Pseudo-random bytes that only compress if the whole block is repeated.
+    #[test]
+    fn test_large_window() {
+        let mut data = random_data(80000);
+        data.extend_from_slice(&data.clone());
+        let small = compress_default(&data, Lz77Options { window_bits: 15, ..Default::default() });
+        for &huffman in &[false, true] {
+            let opts = Lz77Options { window_bits: 17, huffman, ..Default::default() };
+            let compressed = compress_default(&data, opts);
+            assert_eq!(compressed[5], 17);
+            assert_eq!(compressed[6] & 0x04, 0x04);
+            assert!(compressed.len() < small.len() * 2 / 3);
+            assert_eq!(decompress_default(&compressed), data);
+        }
+    }
+
+    #[test]
+    fn test_long_matches() {
+        let data = sample_data(2000).repeat(10);
+        let short = compress_default(&data, Lz77Options::default());
+        let opts = Lz77Options { max_match_len: Some(4000), ..Default::default() };
+        let long = compress_default(&data, opts);
+        assert_eq!(long[6] & 0x04, 0x04);
+        assert!(long.len() < short.len());
+        assert_eq!(decompress_default(&long), data);
+
+        let opts = Lz77Options { max_match_len: Some(4000), raw: true, ..Default::default() };
+        let raw = compress_default(&data, opts.clone());
+        let mut output = Vec::new();
+        let mut decoder = Lz77Decoder::new(Cursor::new(&raw), opts);
+        copy(&mut decoder, &mut output).expect("Decoding failed");
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_optimal_parsing() {
+        let mut repeated = random_data(3000);
+        repeated.extend_from_slice(&repeated.clone());
+        let corpus = [sample_data(20000), random_data(5000), vec![0u8; 5000], repeated,
+                      b"abcdefghij-xab.xabcdefghij".to_vec()];
+        for data in &corpus {
+            for &window_bits in &[9u8, 12, 15, 18] {
+                for &searcher in &[SearcherKind::HashChain, SearcherKind::BinaryTree] {
+                    for &huffman in &[false, true] {
+                        let opts = Lz77Options {
+                            window_bits,
+                            searcher,
+                            huffman,
+                            lazy: 0,
+                            ..Default::default()
+                        };
+                        let mut encoder = Lz77Encoder::<_, AnySearcher>::new(Vec::new(),
+                                                                             opts.clone());
+                        encoder.write_all(data).expect("Encoding failed");
+                        let greedy = encoder.finish().expect("Finishing failed");
+
+                        let opts = Lz77Options { optimal: true, ..opts };
+                        let mut encoder = Lz77Encoder::<_, AnySearcher>::new(Vec::new(), opts);
+                        for chunk in data.chunks(7000) {
+                            encoder.write_all(chunk).expect("Encoding failed");
+                        }
+                        let optimal = encoder.finish().expect("Finishing failed");
+                        assert_eq!(decompress_default(&optimal), &data[..]);
+                        if !huffman {
+                            assert!(optimal.len() <= greedy.len(),
+                                    "optimal {} bytes, greedy {} bytes",
+                                    optimal.len(),
+                                    greedy.len());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_min_match_len() {
+        assert_eq!(Lz77Options::default().match_len_floor(), 3);
+        assert_eq!(Lz77Options { window_bits: 20, ..Default::default() }.match_len_floor(), 5);
+        assert_eq!(Lz77Options { window_bits: 24, ..Default::default() }.match_len_floor(), 6);
+        assert_eq!(Lz77Options { window_bits: 20, huffman: true, ..Default::default() }
+                       .match_len_floor(),
+                   3);
+        let opts = Lz77Options { min_match_len: Some(0), ..Default::default() };
+        assert_eq!(opts.validate(),
+                   Err(Error::InvalidOptions("min_match_len must be at least 1")));
+
+        let data = sample_data(5000);
+        for &kind in &[SearcherKind::Linear, SearcherKind::HashChain, SearcherKind::BinaryTree] {
+            let opts = Lz77Options {
+                searcher: kind,
+                min_match_len: Some(7),
+                ..Default::default()
+            };
+            let mut searcher = AnySearcher::default();
+            searcher.configure(&opts);
+            for pos in 0..(data.len() - 20) {
+                let key = &data[pos..(pos + 16)];
+                let window_start = pos.saturating_sub(4095);
+                let buf = &data[window_start..(pos + 17)];
+                if let Some(res) = searcher.find_longest_match(buf, key) {
+                    assert!(res.length >= 7, "{:?} found {:?}", kind, res);
+                }
+                searcher.insert(pos, &data[pos..(pos + 1)]);
+                searcher.evict((pos + 1).saturating_sub(4095));
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_min_match_len_never_expands() {
+        // With variable-length codewords, short matches at large distances used to cost more
+        // than the literals they replace, so random data grew with the window.
+        let data = random_data(30000);
+        for &window_bits in &[16u8, 20, 24] {
+            let literals = Lz77Options {
+                window_bits,
+                min_match_len: Some(usize::MAX),
+                ..Default::default()
+            };
+            let opts = Lz77Options { window_bits, ..Default::default() };
+            let compressed = compress_default(&data, opts);
+            assert!(compressed.len() <= compress_default(&data, literals).len(),
+                    "window {} expands",
+                    window_bits);
+            assert_eq!(decompress_default(&compressed), data);
+        }
+    }
+
+    #[test]
+    fn test_blocks_round_trip() {
+        for &(block_size, len) in &[(1, 500), (700, 30000), (4096, 30000), (100000, 30000)] {
+            let data = sample_data(len);
+            for &(linked_blocks, block_checksum, huffman, block_index) in
+                &[(false, false, false, false),
+                  (true, true, false, false),
+                  (false, true, true, false),
+                  (true, false, true, false),
+                  (false, false, false, true),
+                  (false, true, true, true)] {
+                let opts = Lz77Options {
+                    block_size: Some(block_size),
+                    linked_blocks,
+                    block_checksum,
+                    huffman,
+                    block_index,
+                    ..Default::default()
+                };
+                let compressed = compress_default(&data, opts.clone());
+                assert_eq!(compressed, ::compress(&data, &opts));
+                assert!(compressed.len() <= ::compress_bound(data.len(), &opts));
+                assert_eq!(decompress_default(&compressed), data);
+                assert_eq!(::decompress(&compressed, &opts), Ok(data.clone()));
+            }
+        }
+    }
+
+    /// Splits a stream with 8-byte block headers after its stream header into its blocks.
+    fn split_blocks(compressed: &[u8]) -> Vec<&[u8]> {
+        let mut blocks = Vec::new();
+        let mut pos = 7;
+        loop {
+            let size = |at: usize| {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(&compressed[at..(at + 4)]);
+                u32::from_be_bytes(bytes) as usize
+            };
+            if size(pos) == 0 {
+                return blocks;
+            }
+            let end = pos + 8 + size(pos);
+            blocks.push(&compressed[pos..end]);
+            pos = end;
+        }
+    }
+
+    #[test]
+    fn test_independent_blocks() {
+        let data = sample_data(10000);
+        let opts = Lz77Options {
+            block_size: Some(2500),
+            checksum: false,
+            ..Default::default()
+        };
+        let independent = compress_default(&data, opts.clone());
+        let blocks = split_blocks(&independent);
+        assert_eq!(blocks.len(), 4);
+        // Every block decodes on its own, as the only block of a stream.
+        for (i, block) in blocks.iter().enumerate() {
+            let mut stream = independent[..7].to_vec();
+            stream.extend_from_slice(block);
+            stream.extend_from_slice(&[0; 4]);
+            assert_eq!(decompress_default(&stream), &data[(2500 * i)..(2500 * (i + 1))]);
+        }
+
+        let linked = compress_default(&data, Lz77Options { linked_blocks: true, ..opts });
+        assert_eq!(linked[6] & 0x20, 0x20);
+        assert!(linked.len() < independent.len());
+        let mut stream = linked[..7].to_vec();
+        stream.extend_from_slice(split_blocks(&linked)[1]);
+        stream.extend_from_slice(&[0; 4]);
+        let mut decoder = Lz77Decoder::new(Cursor::new(&stream), Lz77Options::default());
+        let err = copy(&mut decoder, &mut Vec::new()).expect_err("Linked block decoded alone");
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(&Error::DistanceBeyondWindow { .. }) => {}
+            other => panic!("Expected distance beyond window, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_block_checksum_mismatch() {
+        // Random data is written as literal runs of up to 128 bytes with 9 window bits.
+        let data = random_data(3000);
+        let opts = Lz77Options {
+            window_bits: 9,
+            block_size: Some(1000),
+            block_checksum: true,
+            ..Default::default()
+        };
+        let compressed = compress_default(&data, opts);
+        let block_len = 12 + ::frame::BlockHeader::parse(&compressed[7..19], true).compressed_size;
+        let mut corrupt = compressed.clone();
+        corrupt[7 + block_len + 12 + 10] ^= 0x01;
+        let mut output = Vec::new();
+        let mut decoder = Lz77Decoder::new(Cursor::new(&corrupt), Lz77Options::default());
+        let err = copy(&mut decoder, &mut output).expect_err("Corruption not detected");
+        match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(&Error::BlockChecksumMismatch { offset: 1000, .. }) => {}
+            other => panic!("Expected block checksum mismatch, got {:?}", other),
+        }
+        // The blocks before the corrupt one are intact.
+        assert_eq!(&output[..1000], &data[..1000]);
+
+        // A block whose sizes do not match its tokens is rejected as well.
+        let mut corrupt = compressed.clone();
+        corrupt[14] ^= 0x01;
+        assert!(::decompress(&corrupt, &Lz77Options::default()).is_err());
+    }
+
+    #[test]
+    fn test_flush_ends_block() {
+        let data = sample_data(3000);
+        let opts = Lz77Options { block_size: Some(100000), ..Default::default() };
+        let mut encoder = Lz77Encoder::<_, HashChainSearcher>::new(Vec::new(), opts);
+        encoder.write_all(&data[..1000]).expect("Encoding failed");
+        encoder.flush().expect("Flushing failed");
+        encoder.write_all(&data[1000..]).expect("Encoding failed");
+        let compressed = encoder.finish().expect("Finishing failed");
+        let blocks = split_blocks(&compressed);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(::frame::BlockHeader::parse(blocks[0], false).uncompressed_size, 1000);
+        assert_eq!(decompress_default(&compressed), data);
+    }
+
+    fn json_message(id: u32) -> Vec<u8> {
+        format!("{{\"id\":{},\"type\":\"order\",\"status\":\"shipped\",\"items\":[{{\"sku\":\
+                 \"A{}\",\"quantity\":{}}}],\"customer\":{{\"name\":\"user{}\"}}}}",
+                id,
+                id * 7 % 1000,
+                id % 5 + 1,
+                id * 13 % 97)
+            .into_bytes()
+    }
+
+    fn decode_with_dictionary(compressed: &[u8],
+                              opts: Lz77Options,
+                              dictionary: &[u8])
+                              -> Result<Vec<u8>, Option<Error>> {
+        let mut decoder = Lz77Decoder::with_dictionary(Cursor::new(compressed), opts, dictionary);
+        let mut output = Vec::new();
+        copy(&mut decoder, &mut output)
+            .map(|_| output)
+            .map_err(|e| e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()).cloned())
+    }
+
+    #[test]
+    fn test_dictionary_round_trip() {
+        let dictionary: Vec<u8> = (0..20).flat_map(json_message).collect();
+        let message = json_message(1234);
+        let options = [Lz77Options::default(),
+                       Lz77Options { huffman: true, ..Default::default() },
+                       Lz77Options { optimal: true, ..Default::default() },
+                       Lz77Options { raw: true, ..Default::default() },
+                       // Only the end of the dictionary fits into the window.
+                       Lz77Options { window_bits: 9, ..Default::default() },
+                       Lz77Options { block_size: Some(50), ..Default::default() },
+                       Lz77Options {
+                           block_size: Some(50),
+                           linked_blocks: true,
+                           ..Default::default()
+                       }];
+        for opts in &options {
+            let mut encoder = Lz77Encoder::<_, AnySearcher>::with_dictionary(Vec::new(),
+                                                                             opts.clone(),
+                                                                             &dictionary);
+            encoder.write_all(&message).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert!(compressed.len() < compress_default(&message, opts.clone()).len());
+            assert_eq!(decode_with_dictionary(&compressed, opts.clone(), &dictionary),
+                       Ok(message.clone()));
+        }
+    }
+
+    #[test]
+    fn test_dictionary_mismatch() {
+        let dictionary: Vec<u8> = (0..20).flat_map(json_message).collect();
+        let other: Vec<u8> = (20..40).flat_map(json_message).collect();
+        let (id, other_id) = (::dict::id(&dictionary), ::dict::id(&other));
+        let opts = Lz77Options::default();
+        let mut encoder =
+            Lz77Encoder::<_, AnySearcher>::with_dictionary(Vec::new(), opts.clone(), &dictionary);
+        encoder.write_all(&json_message(1234)).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decode_with_dictionary(&compressed, opts.clone(), &other),
+                   Err(Some(Error::DictionaryMismatch {
+                       expected: Some(id),
+                       actual: Some(other_id),
+                   })));
+        let missing = Err(Error::DictionaryMismatch {
+            expected: Some(id),
+            actual: None,
+        });
+        assert_eq!(::decompress(&compressed, &opts), missing);
+        let plain = compress_default(&json_message(1234), opts.clone());
+        assert_eq!(decode_with_dictionary(&plain, opts, &dictionary),
+                   Err(Some(Error::DictionaryMismatch {
+                       expected: None,
+                       actual: Some(id),
+                   })));
+    }
 }
@@ -0,0 +1,798 @@
+use std::cmp;
+use std::io;
+use ::Lz77Options;
+use searcher::{Searcher, SearchResult, SearcherKind, AnySearcher, SuffixArraySearcher};
+use parser::{self, ParseState, PendingMatch};
+use optimal::Block;
+use token::{Token, TokenWriter};
+use codeword::CodeWordFormat;
+use huffman::{self, BlockDecoder, Symbol};
+use header::{Header, HEADER_SIZE, FLAG_CHECKSUM, FLAG_HUFFMAN, FLAG_VARINT, FLAG_BLOCKS,
+             FLAG_DICTIONARY};
+use frame::{BlockHeader, BlockIndex, END_OF_BLOCKS};
+use checksum::Crc32;
+use decompressor::{Decompressor, Status};
+use errors::{Error, Result};
+
+/// Size of the chunks `decode_framed` decompresses at a time.
+const FRAME_OUTPUT_CHUNK_SIZE: usize = 1 << 16;
+
+/// Parses a complete input held in memory. The window and lookahead are plain subslices of the
+/// input, so searching needs no copies into a ring buffer.
+struct SliceParser<'a, W, S> {
+    input: &'a [u8],
+    pos: usize,
+    /// The window never reaches back before `start`, and parsing stops at `end`.
+    start: usize,
+    end: usize,
+    window_capacity: usize,
+    lookahead_size: usize,
+    options: &'a Lz77Options,
+    searcher: S,
+    tokens: TokenWriter,
+    pending: Option<PendingMatch>,
+    block: Option<Block>,
+    out: W,
+}
+
+impl<'a, W, S> SliceParser<'a, W, S>
+    where W: io::Write,
+          S: Searcher
+{
+    fn new(input: &'a [u8], options: &'a Lz77Options, searcher: S, out: W) -> Self {
+        SliceParser {
+            input,
+            pos: 0,
+            start: 0,
+            end: input.len(),
+            window_capacity: (1 << options.window_bits as usize) - 1,
+            lookahead_size: options.match_len_limit() + 2,
+            options,
+            searcher,
+            tokens: TokenWriter::new(options),
+            pending: None,
+            block: None,
+            out,
+        }
+    }
+
+    fn window_start(&self) -> usize {
+        cmp::max(self.pos.saturating_sub(self.window_capacity), self.start)
+    }
+
+    /// Emits the same codewords as an `Lz77Encoder` that is handed all of the input up to `end`
+    /// in one write and then flushed.
+    fn run(&mut self) -> io::Result<()> {
+        while self.pos < self.end {
+            let remaining = self.end - self.pos;
+            let key_len = if remaining >= self.lookahead_size {
+                self.lookahead_size - 2
+            } else {
+                remaining - 1
+            };
+            parser::step(self, key_len)?;
+        }
+        parser::finish(self)?;
+        self.tokens.flush(&mut self.out)
+    }
+}
+
+impl<'a, W, S> ParseState for SliceParser<'a, W, S>
+    where W: io::Write,
+          S: Searcher
+{
+    fn options(&self) -> &Lz77Options {
+        self.options
+    }
+
+    fn pending(&mut self) -> &mut Option<PendingMatch> {
+        &mut self.pending
+    }
+
+    fn search(&mut self, key_len: usize) -> Option<SearchResult> {
+        let end = cmp::min(self.pos + self.lookahead_size, self.end);
+        let buf = &self.input[self.window_start()..end];
+        self.searcher.find_longest_match(buf, &self.input[self.pos..(self.pos + key_len)])
+    }
+
+    fn search_all(&mut self, key_len: usize, matches: &mut Vec<SearchResult>) {
+        let end = cmp::min(self.pos + self.lookahead_size, self.end);
+        let buf = &self.input[self.window_start()..end];
+        self.searcher.find_matches(buf, &self.input[self.pos..(self.pos + key_len)], matches)
+    }
+
+    fn block(&mut self) -> &mut Option<Block> {
+        &mut self.block
+    }
+
+    fn window_len(&self) -> usize {
+        self.pos - self.window_start()
+    }
+
+    fn window_byte(&self, idx: usize) -> u8 {
+        self.input[self.window_start() + idx]
+    }
+
+    fn lookahead_byte(&self, idx: usize) -> u8 {
+        self.input[self.pos + idx]
+    }
+
+    fn advance(&mut self, n: usize) {
+        let window_start = self.window_start();
+        self.searcher.insert(self.pos, &self.input[self.pos..(self.pos + n)]);
+        self.pos += n;
+        if self.window_start() != window_start {
+            let upto = self.window_start();
+            self.searcher.evict(upto);
+        }
+    }
+
+    fn emit(&mut self, token: Token, next: u8) -> io::Result<()> {
+        self.tokens.write_token(&mut self.out, token, next)
+    }
+
+    fn literal_run_open(&self) -> bool {
+        self.tokens.has_literals()
+    }
+}
+
+fn compress_to<W, S>(input: &[u8],
+                     mut out: W,
+                     options: &Lz77Options,
+                     mut searcher: S)
+                     -> io::Result<W>
+    where W: io::Write,
+          S: Searcher
+{
+    options.validate()?;
+    searcher.configure(options);
+    if !options.raw {
+        let header = Header::from_options(options);
+        out.write_all(&header.as_bytes()[..])?;
+    }
+    if let Some(block_size) = options.frame_block_size() {
+        // The tokens of each block are collected to learn its compressed size.
+        let mut parser = SliceParser::new(input, options, searcher, Vec::new());
+        let index = write_blocks(&mut parser, block_size, &mut out)?;
+        out.write_all(&END_OF_BLOCKS)?;
+        if options.block_index {
+            let mut index_bytes = Vec::with_capacity(BlockIndex::size(index.entries.len()));
+            index.write(&mut index_bytes);
+            out.write_all(&index_bytes)?;
+        }
+    } else {
+        let mut parser = SliceParser::new(input, options, searcher, out);
+        parser.run()?;
+        if !options.raw {
+            parser.tokens.write_end_marker(&mut parser.out)?;
+        }
+        out = parser.out;
+    }
+    if !options.raw && options.checksum {
+        let mut crc = Crc32::new();
+        crc.update(input);
+        out.write_all(&crc.sum().to_be_bytes())?;
+    }
+    Ok(out)
+}
+
+/// Parses the input in blocks of `block_size` bytes and writes each of them to `out`, behind
+/// its block header. Returns the index of the blocks.
+fn write_blocks<W, S>(parser: &mut SliceParser<Vec<u8>, S>,
+                      block_size: usize,
+                      out: &mut W)
+                      -> io::Result<BlockIndex>
+    where W: io::Write,
+          S: Searcher
+{
+    let mut index = BlockIndex::default();
+    let mut block_offset = HEADER_SIZE as u64;
+    let mut block_start = 0;
+    while block_start < parser.input.len() {
+        let block_end = cmp::min(block_start + block_size, parser.input.len());
+        if !parser.options.linked_blocks {
+            parser.start = block_start;
+            parser.searcher.evict(block_start);
+        }
+        index.push(block_offset, block_end - block_start);
+        block_offset += write_block(parser, block_end, out)? as u64;
+        block_start = block_end;
+    }
+    Ok(index)
+}
+
+/// Parses the input from the parser's position up to `block_end` and writes it to `out` as one
+/// block, behind its block header. Returns the number of bytes written.
+fn write_block<W, S>(parser: &mut SliceParser<Vec<u8>, S>,
+                     block_end: usize,
+                     out: &mut W)
+                     -> io::Result<usize>
+    where W: io::Write,
+          S: Searcher
+{
+    let content = &parser.input[parser.pos..block_end];
+    parser.end = block_end;
+    parser.run()?;
+    let header = BlockHeader {
+        compressed_size: parser.out.len(),
+        uncompressed_size: content.len(),
+        checksum: if parser.options.block_checksum {
+            let mut crc = Crc32::new();
+            crc.update(content);
+            Some(crc.sum())
+        } else {
+            None
+        },
+    };
+    let mut header_bytes = Vec::new();
+    header.write(&mut header_bytes);
+    out.write_all(&header_bytes)?;
+    out.write_all(&parser.out)?;
+    let written = header_bytes.len() + parser.out.len();
+    parser.out.clear();
+    Ok(written)
+}
+
+/// Compresses `input[start..]` into a single block with its block header, for a stream split
+/// into blocks with `options`. The window is primed with `input[..start]`, so `start` has to be
+/// 0 unless the stream's blocks are linked.
+pub fn compress_block(input: &[u8], start: usize, options: &Lz77Options) -> Vec<u8> {
+    let window_capacity = (1 << options.window_bits as usize) - 1;
+    let input = &input[start.saturating_sub(window_capacity)..];
+    let start = cmp::min(start, window_capacity);
+    match options.searcher {
+        SearcherKind::SuffixArray => {
+            compress_primed_block(input, start, options, SuffixArraySearcher::new(input))
+        }
+        _ => compress_primed_block(input, start, options, AnySearcher::default()),
+    }
+}
+
+/// Compresses `input[start..]` into a single block with `searcher`, priming it with
+/// `input[..start]`.
+fn compress_primed_block<S>(input: &[u8], start: usize, options: &Lz77Options, mut searcher: S)
+                            -> Vec<u8>
+    where S: Searcher
+{
+    searcher.configure(options);
+    searcher.insert(0, &input[..start]);
+    let mut parser = SliceParser::new(input, options, searcher, Vec::new());
+    parser.pos = start;
+    let bound = tokens_bound(input.len() - start, options);
+    let mut out = Vec::with_capacity(BlockHeader::size(options.block_checksum) + bound);
+    write_block(&mut parser, input.len(), &mut out).expect("Writing to a Vec failed");
+    out
+}
+
+/// Returns the largest size of the codewords for `len` bytes of input.
+fn codewords_bound(len: usize, options: &Lz77Options) -> usize {
+    // A literal run costs a codeword for up to `max_match_len + 1` bytes, a match costs a
+    // codeword and a literal for at least `min_match_len + 1` bytes. The bound assumes whichever
+    // of the two is worse for the whole input.
+    let codeword = CodeWordFormat::new(options.window_bits, options.varint()).max_size();
+    let max_literal_run = options.match_len_limit() + 1;
+    let literals = len + codeword * len.div_ceil(max_literal_run);
+    // Every match may be followed by a single literal that opens a new literal run.
+    let min_match = options.match_len_floor() + 1;
+    let matches = ((2 * codeword + 1) * len).div_ceil(min_match);
+    // Two more codewords cover an end marker.
+    cmp::max(literals, matches) + 2 * codeword
+}
+
+/// Returns the largest size of the tokens for `len` bytes of input in a stream with a header.
+fn tokens_bound(len: usize, options: &Lz77Options) -> usize {
+    if options.huffman {
+        huffman::compress_bound(len,
+                                options.match_len_floor(),
+                                options.window_bits,
+                                options.match_len_limit())
+    } else {
+        let codeword = CodeWordFormat::new(options.window_bits, options.varint()).max_size();
+        codewords_bound(len, options) + codeword
+    }
+}
+
+/// Returns the largest size `compress` can produce for `len` bytes of input with `options`.
+pub fn compress_bound(len: usize, options: &Lz77Options) -> usize {
+    if options.raw {
+        return codewords_bound(len, options);
+    }
+    let tokens = match options.frame_block_size() {
+        Some(size) => {
+            let header = BlockHeader::size(options.block_checksum);
+            let full_blocks = (len / size) * (header + tokens_bound(size, options));
+            let last_block = match len % size {
+                0 => 0,
+                rest => header + tokens_bound(rest, options),
+            };
+            let index = if options.block_index {
+                BlockIndex::size(len.div_ceil(size))
+            } else {
+                0
+            };
+            full_blocks + last_block + END_OF_BLOCKS.len() + index
+        }
+        None => tokens_bound(len, options),
+    };
+    HEADER_SIZE + tokens + if options.checksum { 4 } else { 0 }
+}
+
+/// Compresses `input` into `out` with the searcher chosen by `options.searcher`, building a
+/// `SuffixArraySearcher` over `input` for `SearcherKind::SuffixArray`.
+fn compress_with_options<W>(input: &[u8], out: W, options: &Lz77Options) -> io::Result<W>
+    where W: io::Write
+{
+    match options.searcher {
+        SearcherKind::SuffixArray => {
+            compress_to(input, out, options, SuffixArraySearcher::new(input))
+        }
+        _ => compress_to(input, out, options, AnySearcher::default()),
+    }
+}
+
+/// Compresses `input` in one go, producing the same stream as an `Lz77Encoder` using an
+/// `AnySearcher`. With `SearcherKind::SuffixArray` the matches are as long as the encoder's,
+/// but may start at other positions.
+///
+/// # Panics
+///
+/// Panics if `options` are invalid, see `Lz77Options::validate`.
+pub fn compress(input: &[u8], options: &Lz77Options) -> Vec<u8> {
+    let out = Vec::with_capacity(input.len() / 2);
+    match compress_with_options(input, out, options) {
+        Ok(out) => out,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Compresses `input` into `output` and returns the size of the compressed stream.
+///
+/// Fails with `Error::OutputTooSmall` if the stream does not fit, which can not happen if
+/// `output` holds at least `compress_bound(input.len(), options)` bytes.
+pub fn compress_into(input: &[u8], output: &mut [u8], options: &Lz77Options) -> Result<usize> {
+    let capacity = output.len();
+    let rest = compress_with_options(input, output, options).map_err(|e| {
+            e.into_inner()
+                .and_then(|inner| inner.downcast::<Error>().ok())
+                .map_or(Error::OutputTooSmall, |err| *err)
+        })?;
+    Ok(capacity - rest.len())
+}
+
+/// Destination of `decode`, either growing or of fixed size.
+trait Output {
+    fn written(&self) -> &[u8];
+
+    fn push_slice(&mut self, bytes: &[u8]) -> Result<()>;
+
+    /// Appends `len` bytes copied from `distance` bytes back, which may overlap the appended
+    /// bytes.
+    fn push_match(&mut self, distance: usize, len: usize) -> Result<()>;
+}
+
+impl Output for Vec<u8> {
+    fn written(&self) -> &[u8] {
+        &self[..]
+    }
+
+    fn push_slice(&mut self, bytes: &[u8]) -> Result<()> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn push_match(&mut self, distance: usize, len: usize) -> Result<()> {
+        let start = self.len() - distance;
+        if distance >= len {
+            self.extend_from_within(start..(start + len));
+        } else {
+            for i in start..(start + len) {
+                let c = self[i];
+                self.push(c);
+            }
+        }
+        Ok(())
+    }
+}
+
+struct SliceOutput<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Output for SliceOutput<'a> {
+    fn written(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push_slice(&mut self, bytes: &[u8]) -> Result<()> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(Error::OutputTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn push_match(&mut self, distance: usize, len: usize) -> Result<()> {
+        let end = self.len + len;
+        if end > self.buf.len() {
+            return Err(Error::OutputTooSmall);
+        }
+        let start = self.len - distance;
+        if distance >= len {
+            self.buf.copy_within(start..(start + len), self.len);
+        } else {
+            for i in self.len..end {
+                self.buf[i] = self.buf[i - distance];
+            }
+        }
+        self.len = end;
+        Ok(())
+    }
+}
+
+fn check_token(decoded: usize, distance: usize, length: usize, format: CodeWordFormat) -> Result<()> {
+    if length > format.max_len() {
+        return Err(Error::MatchTooLong {
+            offset: decoded as u64,
+            length,
+        });
+    }
+    if distance == 0 {
+        return Ok(());
+    }
+    let window_len = cmp::min(decoded, (1 << format.window_bits() as usize) - 1);
+    if distance > window_len {
+        return Err(Error::DistanceBeyondWindow {
+            offset: decoded as u64,
+            distance,
+            window_len,
+        });
+    }
+    Ok(())
+}
+
+/// Decodes the codewords starting at `pos`. Returns the position after the end marker, or
+/// `None` if a raw stream ended.
+fn decode_codewords<O>(input: &[u8],
+                       mut pos: usize,
+                       out: &mut O,
+                       options: &Lz77Options,
+                       format: CodeWordFormat)
+                       -> Result<Option<usize>>
+    where O: Output
+{
+    loop {
+        if pos == input.len() {
+            if options.raw {
+                return Ok(None);
+            }
+            return Err(Error::TruncatedStream);
+        }
+        let (distance, length, size) = format.parse(&input[pos..])?
+            .ok_or(Error::TruncatedToken)?;
+        pos += size;
+        if distance != 0 && length == 0 && !options.raw {
+            return Ok(Some(pos));
+        }
+
+        let decoded = out.written().len();
+        check_token(decoded, distance, length, format)?;
+        let token_bytes = if distance == 0 { length + 1 } else { 1 };
+        options.check_output_limit((decoded + length + 1) as u64, (pos + token_bytes) as u64)?;
+        if distance == 0 {
+            if pos + length + 1 > input.len() {
+                return Err(Error::TruncatedToken);
+            }
+            out.push_slice(&input[pos..(pos + length + 1)])?;
+            pos += length + 1;
+            continue;
+        }
+        out.push_match(distance, length)?;
+        if pos == input.len() {
+            return Err(Error::TruncatedToken);
+        }
+        out.push_slice(&input[pos..(pos + 1)])?;
+        pos += 1;
+    }
+}
+
+/// Decodes the entropy-coded blocks starting at `pos` and returns the position after the empty
+/// block ending the stream.
+fn decode_blocks<O>(input: &[u8],
+                    mut pos: usize,
+                    out: &mut O,
+                    options: &Lz77Options,
+                    format: CodeWordFormat)
+                    -> Result<usize>
+    where O: Output
+{
+    loop {
+        if pos + 4 > input.len() {
+            return Err(Error::TruncatedStream);
+        }
+        let mut size_buf = [0u8; 4];
+        size_buf.copy_from_slice(&input[pos..(pos + 4)]);
+        let size = u32::from_be_bytes(size_buf) as usize;
+        pos += 4;
+        if size == 0 {
+            return Ok(pos);
+        }
+        if size > huffman::MAX_BLOCK_SIZE {
+            return Err(Error::CorruptBlock("block too large"));
+        }
+        if pos + size > input.len() {
+            return Err(Error::TruncatedStream);
+        }
+        let mut block = BlockDecoder::new(&input[pos..(pos + size)])?;
+        pos += size;
+        while let Some(symbol) = block.next_symbol()? {
+            let decoded = out.written().len();
+            match symbol {
+                Symbol::Literal(byte) => {
+                    options.check_output_limit(decoded as u64 + 1, pos as u64)?;
+                    out.push_slice(&[byte])?;
+                }
+                Symbol::Match { length, distance } => {
+                    check_token(decoded, distance, length, format)?;
+                    options.check_output_limit((decoded + length) as u64, pos as u64)?;
+                    out.push_match(distance, length)?;
+                }
+            }
+        }
+    }
+}
+
+/// Decodes a stream split into blocks with a `Decompressor`, which keeps track of where the
+/// window of each block starts. Also reports the mismatch of a stream needing a dictionary.
+fn decode_framed<O>(input: &[u8], out: &mut O, options: &Lz77Options) -> Result<()>
+    where O: Output
+{
+    let mut decompressor = Decompressor::new(options.clone());
+    let mut buf = vec![0u8; FRAME_OUTPUT_CHUNK_SIZE];
+    let mut pos = 0;
+    loop {
+        let (consumed, produced, status) = decompressor.decompress(&input[pos..], &mut buf);
+        pos += consumed;
+        out.push_slice(&buf[..produced])?;
+        match status {
+            Status::Done => return Ok(()),
+            Status::OutputFull => {}
+            Status::NeedsInput => return decompressor.finish(),
+            Status::Failed(err) => return Err(err),
+        }
+    }
+}
+
+/// Decodes a complete stream held in memory, reporting the same errors as `Lz77Decoder`.
+fn decode<O>(input: &[u8], out: &mut O, options: &Lz77Options) -> Result<()>
+    where O: Output
+{
+    if options.raw {
+        options.validate()?;
+        let format = CodeWordFormat::new(options.window_bits, options.varint());
+        decode_codewords(input, 0, out, options, format)?;
+        return Ok(());
+    }
+    if input.len() < HEADER_SIZE {
+        return Err(Error::TruncatedStream);
+    }
+    let mut buf = [0u8; HEADER_SIZE];
+    buf.copy_from_slice(&input[..HEADER_SIZE]);
+    let header = Header::parse(&buf)?;
+    if header.has_flag(FLAG_BLOCKS) || header.has_flag(FLAG_DICTIONARY) {
+        return decode_framed(input, out, options);
+    }
+    let format = CodeWordFormat::new(header.window_bits, header.has_flag(FLAG_VARINT));
+    let pos = if header.has_flag(FLAG_HUFFMAN) {
+        decode_blocks(input, HEADER_SIZE, out, options, format)?
+    } else {
+        decode_codewords(input, HEADER_SIZE, out, options, format)?
+            .ok_or(Error::TruncatedStream)?
+    };
+
+    if header.has_flag(FLAG_CHECKSUM) {
+        if pos + 4 > input.len() {
+            return Err(Error::TruncatedStream);
+        }
+        let mut trailer = [0u8; 4];
+        trailer.copy_from_slice(&input[pos..(pos + 4)]);
+        let expected = u32::from_be_bytes(trailer);
+        let mut crc = Crc32::new();
+        crc.update(out.written());
+        let actual = crc.sum();
+        if expected != actual {
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+    }
+    Ok(())
+}
+
+/// Decompresses a complete stream held in memory.
+///
+/// `options` are used as by `Lz77Decoder::new`: only raw streams take the window size from
+/// them.
+pub fn decompress(input: &[u8], options: &Lz77Options) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 2);
+    decode(input, &mut out, options)?;
+    Ok(out)
+}
+
+/// Decompresses a complete stream into `output` and returns the size of the decoded content.
+///
+/// Fails with `Error::OutputTooSmall` if the content does not fit.
+pub fn decompress_into(input: &[u8], output: &mut [u8], options: &Lz77Options) -> Result<usize> {
+    let mut out = SliceOutput {
+        buf: output,
+        len: 0,
+    };
+    decode(input, &mut out, options)?;
+    Ok(out.len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, compress_into, compress_bound, decompress, decompress_into};
+    use encoder::Lz77Encoder;
+    use decoder::Lz77Decoder;
+    use searcher::{AnySearcher, SearcherKind};
+    use errors::Error;
+    use test_util::{sample_data, random_data};
+    use ::Lz77Options;
+    use std::io::{Read, Write};
+
+    fn compress_streaming(data: &[u8], opts: &Lz77Options) -> Vec<u8> {
+        let mut encoder = Lz77Encoder::<_, AnySearcher>::new(Vec::new(), opts.clone());
+        encoder.write_all(data).expect("Encoding failed");
+        encoder.finish().expect("Finish failed")
+    }
+
+    #[test]
+    fn test_compress_matches_encoder() {
+        let data = sample_data(8000);
+        for &level in &[1u8, 4, 6, 9] {
+            for &window_bits in &[9u8, 12, 15, 18] {
+                for &(raw, huffman) in &[(false, false), (true, false), (false, true)] {
+                    let opts = Lz77Options {
+                        window_bits,
+                        raw,
+                        huffman,
+                        ..Lz77Options::with_level(level)
+                    };
+                    let compressed = compress(&data, &opts);
+                    assert_eq!(compressed, compress_streaming(&data, &opts));
+                    assert_eq!(decompress(&compressed, &opts), Ok(data.clone()));
+                }
+            }
+        }
+        let opts = Lz77Options { max_match_len: Some(1000), ..Default::default() };
+        let compressed = compress(&data, &opts);
+        assert_eq!(compressed, compress_streaming(&data, &opts));
+        assert_eq!(decompress(&compressed, &opts), Ok(data.clone()));
+
+        let opts = Lz77Options::default();
+        assert_eq!(compress(b"", &opts), compress_streaming(b"", &opts));
+        assert_eq!(decompress(&compress(b"", &opts), &opts), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_compress_suffix_array() {
+        let inputs = [sample_data(20000), random_data(3000), vec![0u8; 3000]];
+        for data in &inputs {
+            for &window_bits in &[9u8, 12, 15, 18] {
+                for &optimal in &[false, true] {
+                    let opts = Lz77Options {
+                        window_bits,
+                        optimal,
+                        searcher: SearcherKind::BinaryTree,
+                        ..Default::default()
+                    };
+                    let whole_buffer = Lz77Options {
+                        searcher: SearcherKind::SuffixArray,
+                        ..opts.clone()
+                    };
+                    let compressed = compress(data, &whole_buffer);
+                    let mut output = Vec::new();
+                    let mut decoder = Lz77Decoder::new(&compressed[..], Lz77Options::default());
+                    decoder.read_to_end(&mut output).expect("Decoding failed");
+                    assert_eq!(&output, data);
+                    // Both find the longest match, which is all that matters for fixed-size
+                    // codewords.
+                    if !optimal && window_bits <= 15 {
+                        assert_eq!(compressed.len(), compress(data, &opts).len());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_into() {
+        let data = sample_data(5000);
+        let opts = Lz77Options::default();
+        let expected = compress(&data, &opts);
+        let mut output = vec![0u8; compress_bound(data.len(), &opts)];
+        let size = compress_into(&data, &mut output, &opts).expect("Compression failed");
+        assert_eq!(&output[..size], &expected[..]);
+
+        let mut exact = vec![0u8; expected.len()];
+        assert_eq!(compress_into(&data, &mut exact, &opts), Ok(expected.len()));
+        let mut small = vec![0u8; expected.len() - 1];
+        assert_eq!(compress_into(&data, &mut small, &opts), Err(Error::OutputTooSmall));
+    }
+
+    #[test]
+    fn test_decompress_into() {
+        let data = sample_data(5000);
+        let opts = Lz77Options::default();
+        let compressed = compress(&data, &opts);
+        let mut output = vec![0u8; data.len()];
+        assert_eq!(decompress_into(&compressed, &mut output, &opts), Ok(data.len()));
+        assert_eq!(output, data);
+        let mut small = vec![0u8; data.len() - 1];
+        assert_eq!(decompress_into(&compressed, &mut small, &opts), Err(Error::OutputTooSmall));
+    }
+
+    #[test]
+    fn test_compress_bound() {
+        let inputs = [random_data(3000), sample_data(3000), vec![0u8; 3000], b"a".to_vec()];
+        for data in &inputs {
+            for &window_bits in &[9u8, 12, 15, 18] {
+                for &min_match_len in &[1usize, 2, 3, 8] {
+                    for &searcher in &[SearcherKind::Linear, SearcherKind::HashChain] {
+                        for &huffman in &[false, true] {
+                            let opts = Lz77Options {
+                                window_bits,
+                                min_match_len: Some(min_match_len),
+                                searcher,
+                                huffman,
+                                ..Default::default()
+                            };
+                            let compressed = compress(&data[..], &opts);
+                            assert!(compressed.len() <= compress_bound(data.len(), &opts));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompress_errors() {
+        let opts = Lz77Options::default();
+        let compressed = compress(&sample_data(1000), &opts);
+        assert_eq!(decompress(&compressed[..3], &opts), Err(Error::TruncatedStream));
+        assert_eq!(decompress(&compressed[..compressed.len() - 6], &opts),
+                   Err(Error::TruncatedStream));
+        assert_eq!(decompress(b"LZ88\x01\x0c\x01", &opts), Err(Error::InvalidMagic));
+
+        let mut corrupt = compressed.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        match decompress(&corrupt, &opts) {
+            Err(Error::ChecksumMismatch { .. }) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let raw = Lz77Options { raw: true, ..Default::default() };
+        assert_eq!(decompress(&[0x00, 0x01, b'a', b'b', 0x00, 0x32, b'c'], &raw),
+                   Err(Error::DistanceBeyondWindow {
+                       offset: 2,
+                       distance: 3,
+                       window_len: 2,
+                   }));
+        assert_eq!(decompress(&[0x00, 0x03, b'a'], &raw), Err(Error::TruncatedToken));
+    }
+
+    #[test]
+    fn test_decompress_output_limit() {
+        let data = vec![0u8; 100000];
+        let compressed = compress(&data, &Lz77Options::default());
+        let limited = Lz77Options { max_output_size: Some(1000), ..Default::default() };
+        assert_eq!(decompress(&compressed, &limited),
+                   Err(Error::OutputLimitExceeded { limit: 1000 }));
+        let limited = Lz77Options { max_output_size: Some(100000), ..Default::default() };
+        assert_eq!(decompress(&compressed, &limited), Ok(data));
+    }
+}
@@ -0,0 +1,317 @@
+use std::cmp;
+use std::io;
+use ::Lz77Options;
+use codeword::CodeWordFormat;
+use parser::ParseState;
+use searcher::SearchResult;
+use token::Token;
+
+/// Largest number of positions the optimal parser chooses tokens for at once.
+const MAX_BLOCK_LEN: usize = 4096;
+/// Matches longer than this are only considered at their full length, which keeps the work
+/// per position bounded for long matches.
+const NICE_LEN: usize = 256;
+
+#[derive(Clone, Copy)]
+enum Choice {
+    Literal,
+    Match(usize, usize),
+}
+
+/// The cheapest tokens found for the bytes before a position.
+#[derive(Clone, Copy)]
+struct Node {
+    cost: usize,
+    /// Length of the literal run open after the tokens, `0` if they end with a match.
+    run: usize,
+    /// Start of the last token and the slot of the tokens before it.
+    from: usize,
+    from_slot: usize,
+    choice: Choice,
+}
+
+const UNREACHED: Node = Node {
+    cost: usize::MAX,
+    run: 0,
+    from: 0,
+    from_slot: 0,
+    choice: Choice::Literal,
+};
+
+/// Slot of the cheapest tokens ending with a match. The next literal opens a new run after
+/// them, so tokens ending with a literal are kept in the other slots if they are cheaper, or
+/// leave more room in their run while costing less than a run header more.
+const MATCH_SLOT: usize = 0;
+const SLOTS: usize = 3;
+
+/// Candidate matches for the positions moved into the window since tokens were last emitted.
+///
+/// Once the block is full, the cheapest token sequence covering it is found by relaxing every
+/// literal and every match length from each position in order, like a shortest path search.
+/// Matches are cut off at the end of the block, so the tokens close to it are kept back and
+/// chosen again as part of the next block.
+pub struct Block {
+    format: CodeWordFormat,
+    huffman: bool,
+    min_match_len: usize,
+    max_literal_run: usize,
+    max_len: usize,
+    /// Length of the literal run open before the first position.
+    initial_run: usize,
+    /// Candidates of all positions as distance and length, the lengths of each position
+    /// increasing.
+    candidates: Vec<(usize, usize)>,
+    /// Index of the first candidate of each position.
+    starts: Vec<usize>,
+    found: Vec<SearchResult>,
+    /// The cheapest tokens reaching every position, see `MATCH_SLOT`.
+    nodes: Vec<[Node; SLOTS]>,
+    path: Vec<(usize, Choice)>,
+}
+
+impl Block {
+    pub fn new(options: &Lz77Options) -> Self {
+        Block {
+            format: CodeWordFormat::new(options.window_bits, options.varint()),
+            huffman: options.huffman && !options.raw,
+            min_match_len: options.match_len_floor(),
+            max_literal_run: options.match_len_limit() + 1,
+            max_len: cmp::min(MAX_BLOCK_LEN, (1 << options.window_bits as usize) - 1),
+            initial_run: 0,
+            candidates: Vec::new(),
+            starts: Vec::new(),
+            found: Vec::new(),
+            nodes: Vec::new(),
+            path: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Size of a literal following a literal run of length `run`, or a match if `run` is 0.
+    fn literal_cost(&self, run: usize) -> usize {
+        if self.huffman || (run > 0 && run < self.max_literal_run) {
+            1
+        } else {
+            1 + self.format.size(0, 0)
+        }
+    }
+
+    fn relax_match(&mut self, to: usize, node: Node) {
+        let slot = &mut self.nodes[to][MATCH_SLOT];
+        // Ties go to the later start, so that shorter matches end up at the end of the block.
+        if node.cost <= slot.cost {
+            *slot = node;
+        }
+    }
+
+    fn relax_literal(&mut self, to: usize, node: Node) {
+        let header = if self.huffman { 0 } else { self.format.size(0, 0) };
+        let mut nodes = [self.nodes[to][1], self.nodes[to][2], node];
+        nodes.sort_by_key(|n| (n.cost, n.run));
+        let slots = &mut self.nodes[to];
+        slots[1] = nodes[0];
+        slots[2] = UNREACHED;
+        for n in &nodes[1..] {
+            if n.cost < nodes[0].cost.saturating_add(header) && n.run < nodes[0].run {
+                slots[2] = *n;
+                break;
+            }
+        }
+    }
+
+    /// Finds the cheapest tokens for the block and returns them in `path`.
+    fn find_path(&mut self) {
+        let len = self.starts.len();
+        self.nodes.clear();
+        self.nodes.resize(len + 1, [UNREACHED; SLOTS]);
+        let slot = if self.initial_run == 0 { MATCH_SLOT } else { 1 };
+        self.nodes[0][slot] = Node {
+            cost: 0,
+            run: self.initial_run,
+            ..UNREACHED
+        };
+
+        for i in 0..len {
+            let mut cheapest = UNREACHED;
+            for slot in 0..SLOTS {
+                let node = self.nodes[i][slot];
+                if node.cost == usize::MAX {
+                    continue;
+                }
+                let run = if node.run == self.max_literal_run { 1 } else { node.run + 1 };
+                self.relax_literal(i + 1,
+                                   Node {
+                                       cost: node.cost + self.literal_cost(node.run),
+                                       run,
+                                       from: i,
+                                       from_slot: slot,
+                                       choice: Choice::Literal,
+                                   });
+                if node.cost < cheapest.cost {
+                    cheapest = Node { from_slot: slot, ..node };
+                }
+            }
+
+            // Each length is taken from the closest candidate that is long enough. A match is
+            // followed by a literal, which has to lie inside the block.
+            let end = if i + 1 < len { self.starts[i + 1] } else { self.candidates.len() };
+            let mut length = self.min_match_len;
+            for c in self.starts[i]..end {
+                let (distance, max_length) = self.candidates[c];
+                let max_length = cmp::min(max_length, len - i - 1);
+                // Past `NICE_LEN`, only the full length of each candidate is tried.
+                if length > NICE_LEN {
+                    length = cmp::max(length, max_length);
+                }
+                while length <= max_length {
+                    self.relax_match(i + length + 1,
+                                     Node {
+                                         cost: cheapest.cost +
+                                               self.format.size(distance, length) +
+                                               1,
+                                         run: 0,
+                                         from: i,
+                                         from_slot: cheapest.from_slot,
+                                         choice: Choice::Match(distance, length),
+                                     });
+                    length = if length >= NICE_LEN && length < max_length {
+                        max_length
+                    } else {
+                        length + 1
+                    };
+                }
+            }
+        }
+
+        self.path.clear();
+        let mut pos = len;
+        let mut slot = (0..SLOTS).min_by_key(|&slot| self.nodes[len][slot].cost).unwrap_or(0);
+        while pos > 0 {
+            let node = self.nodes[pos][slot];
+            self.path.push((node.from, node.choice));
+            pos = node.from;
+            slot = node.from_slot;
+        }
+        self.path.reverse();
+    }
+
+    /// Returns the last position that the cheapest tokens reaching the end of the block in
+    /// each slot all pass through. The tokens before it are chosen no matter which of them
+    /// the bytes after the block favour.
+    fn converged(&self) -> usize {
+        let len = self.starts.len();
+        let mut ends: Vec<(usize, usize)> = (0..SLOTS)
+            .filter(|&slot| self.nodes[len][slot].cost != usize::MAX)
+            .map(|slot| (len, slot))
+            .collect();
+        while ends.iter().any(|&end| end != ends[0]) {
+            let idx = (0..ends.len()).max_by_key(|&idx| ends[idx].0).unwrap_or(0);
+            let node = self.nodes[ends[idx].0][ends[idx].1];
+            ends[idx] = (node.from, node.from_slot);
+        }
+        ends.first().map_or(0, |end| end.0)
+    }
+
+    /// Emits the cheapest tokens for the block, which ends at the end of the window. Unless
+    /// `all` is set, the tokens close to the end of the block are kept back.
+    fn emit<P>(&mut self, state: &mut P, all: bool) -> io::Result<()>
+        where P: ParseState
+    {
+        self.find_path();
+        let len = self.starts.len();
+        // Keep back the tokens that might have been chosen differently with the bytes after
+        // the block, unless that would keep back all of them.
+        let margin = cmp::min(self.max_literal_run, len / 2);
+        let cut = if all {
+            len
+        } else {
+            let converged = self.converged();
+            let limit = if converged > 0 {
+                cmp::min(converged, len - margin)
+            } else {
+                len - margin
+            };
+            match self.path.iter().rposition(|&(pos, _)| pos > 0 && pos <= limit) {
+                Some(idx) => self.path[idx].0,
+                None => len,
+            }
+        };
+
+        let start = state.window_len() - len;
+        let mut run = self.initial_run;
+        for &(pos, choice) in &self.path {
+            if pos >= cut {
+                break;
+            }
+            match choice {
+                Choice::Literal => {
+                    let literal = state.window_byte(start + pos);
+                    state.emit(Token::Literal, literal)?;
+                    run = if run == self.max_literal_run { 1 } else { run + 1 };
+                }
+                Choice::Match(distance, length) => {
+                    let next = state.window_byte(start + pos + length);
+                    state.emit(Token::Match(distance, length), next)?;
+                    run = 0;
+                }
+            }
+        }
+        let kept = if cut < len { self.starts[cut] } else { self.candidates.len() };
+        self.candidates.drain(..kept);
+        self.starts.drain(..cut);
+        for start in &mut self.starts {
+            *start -= kept;
+        }
+        self.initial_run = run;
+        Ok(())
+    }
+}
+
+/// Collects the candidate matches for the next lookahead byte and moves it into the window.
+/// Emits the tokens for the block once it is full.
+pub fn step<P>(state: &mut P, key_len: usize) -> io::Result<()>
+    where P: ParseState
+{
+    let mut block = match state.block().take() {
+        Some(block) => block,
+        None => Block::new(state.options()),
+    };
+    if block.is_empty() {
+        block.initial_run = if state.literal_run_open() { 1 } else { 0 };
+    }
+
+    block.found.clear();
+    state.search_all(key_len, &mut block.found);
+    let window_len = state.window_len();
+    block.starts.push(block.candidates.len());
+    for res in &block.found {
+        if res.length >= block.min_match_len {
+            block.candidates.push((window_len - res.position, res.length));
+        }
+    }
+    state.advance(1);
+
+    let res = if block.starts.len() == block.max_len {
+        block.emit(state, false)
+    } else {
+        Ok(())
+    };
+    *state.block() = Some(block);
+    res
+}
+
+/// Emits the tokens for the bytes collected so far.
+pub fn finish<P>(state: &mut P) -> io::Result<()>
+    where P: ParseState
+{
+    let mut block = match state.block().take() {
+        Some(block) => block,
+        None => return Ok(()),
+    };
+    let res = if block.is_empty() { Ok(()) } else { block.emit(state, true) };
+    *state.block() = Some(block);
+    res
+}
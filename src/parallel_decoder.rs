@@ -0,0 +1,515 @@
+use std::cmp;
+use std::io;
+use ::{Lz77Options, MAX_BLOCK_SIZE};
+use header::{Header, HEADER_SIZE, FLAG_BLOCKS, FLAG_BLOCK_CHECKSUM, FLAG_BLOCK_INDEX,
+             FLAG_CHECKSUM, FLAG_LINKED_BLOCKS, FLAG_DICTIONARY};
+use frame::{self, decode_block, BlockHeader, BlockIndex, END_OF_BLOCKS};
+use checksum::Crc32;
+use decompressor::{Decompressor, Status};
+use errors::{Error, Result};
+use pool::OrderedPool;
+
+/// Size of the chunks read from the inner reader.
+const INPUT_BUFFER_SIZE: usize = 1 << 14;
+
+/// Decodes a stream read from `R`, decoding its blocks on a pool of worker threads if they are
+/// independent of each other. Accepts the same streams and options as `Lz77Decoder`, streams
+/// without blocks or with linked blocks are decoded on the calling thread. Streams encoded with
+/// a dictionary are not supported.
+///
+/// Blocks are read ahead of the output handed out, at most two per thread, so no more than that
+/// many blocks are held in memory, compressed or not. As blocks may hold up to `MAX_BLOCK_SIZE`
+/// bytes, that can take several GiB for streams with large blocks. Buffers only grow with the
+/// data actually read and decoded, not with the sizes block headers claim, so with untrusted
+/// input `max_output_size` or `max_expansion_ratio` bound the memory used. The inner reader is
+/// read in chunks, so it may be read past the end of the stream.
+pub struct ParallelLz77Decoder<R>
+    where R: io::Read
+{
+    input: Input<R>,
+    options: Lz77Options,
+    threads: usize,
+    mode: Mode,
+    /// Content of the current block not handed out yet, from `output_pos` on.
+    output: Vec<u8>,
+    output_pos: usize,
+}
+
+enum Mode {
+    Header,
+    Sequential(Box<Decompressor>),
+    Parallel(Blocks),
+    Done,
+    Failed(Error),
+}
+
+/// Reader state of a stream whose blocks are decoded by the pool.
+struct Blocks {
+    pool: OrderedPool<BlockJob, Result<Vec<u8>>>,
+    max_pending: usize,
+    /// Header of a stream of a single block, put in front of every block handed to the pool.
+    block_stream_header: Header,
+    block_checksums: bool,
+    block_index: bool,
+    checksum: Option<Crc32>,
+    /// Number, content and compressed sizes of the blocks read so far.
+    blocks: usize,
+    decoded: u64,
+    consumed: u64,
+    /// Set once the end of blocks is read, with the checksum from the trailer if there is one.
+    trailer: Option<Option<u32>>,
+    /// Error reading ahead, reported once the blocks before it are handed out.
+    read_error: Option<io::Error>,
+}
+
+/// A block to decode, as a complete stream containing only that block.
+struct BlockJob {
+    stream: Vec<u8>,
+    /// Decoded offset and size of the block.
+    start: u64,
+    size: usize,
+}
+
+/// Buffered input, read in chunks from the inner reader.
+struct Input<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R> Input<R>
+    where R: io::Read
+{
+    fn available(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Reads from the inner reader until at least `n` bytes are buffered. Returns whether there
+    /// are, which is only not the case at the end of the input.
+    fn fill(&mut self, n: usize) -> io::Result<bool> {
+        if self.buf.len() - self.pos >= n {
+            return Ok(true);
+        }
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        while self.buf.len() < n && !self.eof {
+            // Grows with what was read, not with what `n` claims.
+            let len = self.buf.len();
+            self.buf.resize(cmp::max(len + INPUT_BUFFER_SIZE, cmp::min(n, 2 * len)), 0);
+            let res = self.inner.read(&mut self.buf[len..]);
+            self.buf.truncate(len + *res.as_ref().unwrap_or(&0));
+            match res {
+                Ok(read) => self.eof = read == 0,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.buf.len() >= n)
+    }
+
+    /// Returns the next `n` bytes, failing with `TruncatedStream` if the input ends before.
+    /// Nothing is consumed if reading them fails, so the call can be retried.
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        if !self.fill(n)? {
+            return Err(Error::TruncatedStream.into());
+        }
+        self.pos += n;
+        Ok(&self.buf[(self.pos - n)..self.pos])
+    }
+}
+
+/// Decodes the block in `job.stream`.
+fn decode_job(job: BlockJob) -> Result<Vec<u8>> {
+    decode_block(&job.stream, job.start, job.size)
+}
+
+impl Blocks {
+    fn new(header: Header, threads: usize) -> Self {
+        let block_stream_header = Header {
+            flags: header.flags & !(FLAG_CHECKSUM | FLAG_BLOCK_INDEX),
+            ..header
+        };
+        let pool = OrderedPool::new(threads, decode_job);
+        Blocks {
+            max_pending: 2 * pool.threads(),
+            pool,
+            block_stream_header,
+            block_checksums: header.has_flag(FLAG_BLOCK_CHECKSUM),
+            block_index: header.has_flag(FLAG_BLOCK_INDEX),
+            checksum: if header.has_flag(FLAG_CHECKSUM) { Some(Crc32::new()) } else { None },
+            blocks: 0,
+            decoded: 0,
+            consumed: HEADER_SIZE as u64,
+            trailer: None,
+            read_error: None,
+        }
+    }
+
+    /// Reads the next block and hands it to the pool, or reads the trailer after the last one.
+    /// Nothing is consumed unless all of it could be read, so failing calls can be retried.
+    fn read_block<R: io::Read>(&mut self,
+                               input: &mut Input<R>,
+                               options: &Lz77Options)
+                               -> io::Result<()> {
+        if input.fill(END_OF_BLOCKS.len())? && input.available().starts_with(&END_OF_BLOCKS) {
+            return self.read_trailer(input);
+        }
+        let header_size = BlockHeader::size(self.block_checksums);
+        if !input.fill(header_size)? {
+            return Err(Error::TruncatedStream.into());
+        }
+        let header = BlockHeader::parse(input.available(), self.block_checksums);
+        if header.uncompressed_size > MAX_BLOCK_SIZE {
+            return Err(Error::CorruptBlock("block is larger than MAX_BLOCK_SIZE").into());
+        }
+        if header.compressed_size as u64 >
+           frame::max_compressed_size(header.uncompressed_size, &self.block_stream_header) {
+            return Err(Error::CorruptBlock("compressed block is larger than possible").into());
+        }
+        let consumed = self.consumed + (header_size + header.compressed_size) as u64;
+        let decoded = self.decoded + header.uncompressed_size as u64;
+        options.check_output_limit(decoded, consumed)?;
+
+        let block = input.take(header_size + header.compressed_size)?;
+        let mut stream = self.block_stream_header.as_bytes().to_vec();
+        stream.extend_from_slice(block);
+        stream.extend_from_slice(&END_OF_BLOCKS);
+        self.pool.submit(BlockJob {
+            stream,
+            start: self.decoded,
+            size: header.uncompressed_size,
+        });
+        self.blocks += 1;
+        self.consumed = consumed;
+        self.decoded = decoded;
+        Ok(())
+    }
+
+    /// Reads the end of blocks, the block index if there is one, which has to match the blocks
+    /// read, and the trailer.
+    fn read_trailer<R: io::Read>(&mut self, input: &mut Input<R>) -> io::Result<()> {
+        let mut size = END_OF_BLOCKS.len();
+        let mut index_size = 0;
+        if self.block_index {
+            if !input.fill(size + 4)? {
+                return Err(Error::TruncatedStream.into());
+            }
+            let count = frame::read_u32(&input.available()[size..], 0) as usize;
+            index_size = BlockIndex::size(count);
+        }
+        let trailer_size = if self.checksum.is_some() { 4 } else { 0 };
+        let trailer = input.take(size + index_size + trailer_size)?;
+        if self.block_index {
+            let index = BlockIndex::parse(&trailer[size..(size + index_size)])?;
+            if index.entries.len() != self.blocks || index.content_size != self.decoded {
+                return Err(Error::CorruptBlock("block index does not match the blocks").into());
+            }
+        }
+        size += index_size;
+        self.trailer = Some(if trailer_size > 0 {
+            Some(frame::read_u32(trailer, size))
+        } else {
+            None
+        });
+        Ok(())
+    }
+
+    /// Returns the content of the next block, or `None` after the last one.
+    fn next_block<R: io::Read>(&mut self,
+                               input: &mut Input<R>,
+                               options: &Lz77Options)
+                               -> io::Result<Option<Vec<u8>>> {
+        while self.trailer.is_none() && self.read_error.is_none() &&
+              self.pool.pending() < self.max_pending {
+            if let Err(err) = self.read_block(input, options) {
+                self.read_error = Some(err);
+            }
+        }
+        if let Some(block) = self.pool.next_result() {
+            let block = block?;
+            if let Some(ref mut crc) = self.checksum {
+                crc.update(&block);
+            }
+            return Ok(Some(block));
+        }
+        if let Some(err) = self.read_error.take() {
+            return Err(err);
+        }
+        if let (Some(crc), Some(Some(expected))) = (self.checksum.take(), self.trailer) {
+            if crc.sum() != expected {
+                return Err(Error::ChecksumMismatch {
+                        expected,
+                        actual: crc.sum(),
+                    }
+                    .into());
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R> ParallelLz77Decoder<R>
+    where R: io::Read
+{
+    /// Creates a decoder reading from `reader` and decoding on `threads` worker threads, or on
+    /// one per available CPU if `threads` is 0. See `Lz77Decoder::new` for how `options` are
+    /// used.
+    pub fn new(reader: R, options: Lz77Options, threads: usize) -> Self {
+        ParallelLz77Decoder {
+            input: Input {
+                inner: reader,
+                buf: Vec::with_capacity(INPUT_BUFFER_SIZE),
+                pos: 0,
+                eof: false,
+            },
+            options,
+            threads,
+            mode: Mode::Header,
+            output: Vec::new(),
+            output_pos: 0,
+        }
+    }
+
+    /// Picks how to decode the stream from its header, which is left in the input for a
+    /// decompressor on the calling thread.
+    fn read_header(&mut self) -> io::Result<Mode> {
+        if self.options.raw || !self.input.fill(HEADER_SIZE)? {
+            // Raw streams have no header, a truncated one is reported by the decompressor.
+            return Ok(Mode::Sequential(Box::new(Decompressor::new(self.options.clone()))));
+        }
+        let mut buf = [0; HEADER_SIZE];
+        buf.copy_from_slice(&self.input.available()[..HEADER_SIZE]);
+        let header = Header::parse(&buf)?;
+        // A stream needing a dictionary is left to the decompressor to report the mismatch.
+        if !header.has_flag(FLAG_BLOCKS) || header.has_flag(FLAG_LINKED_BLOCKS) ||
+           header.has_flag(FLAG_DICTIONARY) {
+            return Ok(Mode::Sequential(Box::new(Decompressor::new(self.options.clone()))));
+        }
+        self.input.take(HEADER_SIZE)?;
+        Ok(Mode::Parallel(Blocks::new(header, self.threads)))
+    }
+}
+
+/// Decodes into `buf` on the calling thread, like `Lz77Decoder`.
+fn read_sequential<R: io::Read>(decompressor: &mut Decompressor,
+                                input: &mut Input<R>,
+                                buf: &mut [u8])
+                                -> io::Result<usize> {
+    let mut written = 0;
+    loop {
+        if !decompressor.is_done() {
+            input.fill(1)?;
+        }
+        let (consumed, produced, status) =
+            decompressor.decompress(input.available(), &mut buf[written..]);
+        input.pos += consumed;
+        written += produced;
+        match status {
+            Status::Done | Status::OutputFull => return Ok(written),
+            // The bytes decoded before the error are handed out first.
+            Status::Failed(_) if written > 0 => return Ok(written),
+            Status::Failed(err) => return Err(err.into()),
+            Status::NeedsInput if input.eof => {
+                decompressor.finish()?;
+                return Ok(written);
+            }
+            Status::NeedsInput => {}
+        }
+    }
+}
+
+impl<R> io::Read for ParallelLz77Decoder<R>
+    where R: io::Read
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.output_pos < self.output.len() {
+                let n = cmp::min(buf.len(), self.output.len() - self.output_pos);
+                buf[..n].copy_from_slice(&self.output[self.output_pos..(self.output_pos + n)]);
+                self.output_pos += n;
+                return Ok(n);
+            }
+            let res = match self.mode {
+                Mode::Header => self.read_header(),
+                Mode::Sequential(ref mut decompressor) => {
+                    return read_sequential(decompressor, &mut self.input, buf);
+                }
+                Mode::Parallel(ref mut blocks) => {
+                    match blocks.next_block(&mut self.input, &self.options) {
+                        Ok(Some(block)) => {
+                            self.output = block;
+                            self.output_pos = 0;
+                            continue;
+                        }
+                        Ok(None) => Ok(Mode::Done),
+                        Err(err) => Err(err),
+                    }
+                }
+                Mode::Done => return Ok(0),
+                Mode::Failed(ref err) => return Err(err.clone().into()),
+            };
+            // Replacing a parallel mode stops the pool's workers.
+            self.mode = match res {
+                Ok(mode) => mode,
+                Err(err) => {
+                    match err.get_ref().and_then(|inner| inner.downcast_ref::<Error>()).cloned() {
+                        Some(err) => Mode::Failed(err),
+                        // I/O errors of the inner reader are passed on as they are. The block or
+                        // trailer they interrupted was not consumed, so reading again retries.
+                        None => return Err(err),
+                    }
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParallelLz77Decoder;
+    use std::cmp;
+    use std::io::{self, Read};
+    use errors::Error;
+    use test_util::sample_data;
+    use ::{compress, Lz77Options};
+
+    fn decode(data: &[u8], opts: Lz77Options, threads: usize) -> Result<Vec<u8>, Option<Error>> {
+        let mut decoder = ParallelLz77Decoder::new(data, opts, threads);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output)
+            .map(|_| output)
+            .map_err(|e| e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()).cloned())
+    }
+
+    #[test]
+    fn test_parallel_decoder() {
+        let data = sample_data(60000);
+        let options = [Lz77Options { block_size: Some(3000), ..Default::default() },
+                       Lz77Options {
+                           block_size: Some(1024),
+                           block_checksum: true,
+                           block_index: true,
+                           huffman: true,
+                           ..Default::default()
+                       },
+                       Lz77Options {
+                           block_size: Some(70000),
+                           checksum: false,
+                           window_bits: 20,
+                           ..Default::default()
+                       },
+                       // Decoded on the calling thread.
+                       Lz77Options {
+                           block_size: Some(3000),
+                           linked_blocks: true,
+                           ..Default::default()
+                       },
+                       Lz77Options::default(),
+                       Lz77Options { raw: true, ..Default::default() }];
+        for opts in &options {
+            let compressed = compress(&data, opts);
+            for &threads in &[1, 3] {
+                assert_eq!(decode(&compressed, opts.clone(), threads).unwrap(), data);
+            }
+        }
+        let opts = Lz77Options { block_size: Some(100), ..Default::default() };
+        assert_eq!(decode(&compress(b"", &opts), opts, 2).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_parallel_decoder_errors() {
+        let data = sample_data(20000);
+        let opts = Lz77Options {
+            block_size: Some(1000),
+            block_checksum: true,
+            ..Default::default()
+        };
+        let compressed = compress(&data, &opts);
+        assert_eq!(decode(&compressed[..compressed.len() - 3], opts.clone(), 2),
+                   Err(Some(Error::TruncatedStream)));
+        assert_eq!(decode(&compressed[..compressed.len() - 500], opts.clone(), 2),
+                   Err(Some(Error::TruncatedStream)));
+
+        let mut corrupt = compressed.clone();
+        let len = corrupt.len();
+        corrupt[len - 1] ^= 1;
+        assert!(matches!(decode(&corrupt, opts.clone(), 2),
+                         Err(Some(Error::ChecksumMismatch { .. }))));
+
+        // The first block header follows the stream header, the payload its 12 bytes.
+        let mut corrupt = compressed.clone();
+        let second_block = 7 + 12 + u32::from_be_bytes([corrupt[7], corrupt[8], corrupt[9],
+                                                        corrupt[10]]) as usize;
+        corrupt[second_block + 12 + 1] ^= 0x40;
+        match decode(&corrupt, opts.clone(), 2) {
+            Err(Some(Error::BlockChecksumMismatch { offset, .. })) => assert_eq!(offset, 1000),
+            Err(Some(Error::DistanceBeyondWindow { offset, .. })) => assert!(offset >= 1000),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let limited = Lz77Options { max_output_size: Some(5000), ..opts };
+        assert_eq!(decode(&compressed, limited, 2),
+                   Err(Some(Error::OutputLimitExceeded { limit: 5000 })));
+    }
+
+    #[test]
+    fn test_parallel_decoder_rejects_oversized_blocks() {
+        let opts = Lz77Options { block_size: Some(1000), ..Default::default() };
+        let mut stream = compress(b"", &opts)[..7].to_vec();
+        stream.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0x10, 0]);
+        assert_eq!(decode(&stream, opts, 2),
+                   Err(Some(Error::CorruptBlock("compressed block is larger than possible"))));
+    }
+
+    /// Reader handing out a few bytes at a time, failing once at every offset in `failures`.
+    struct FlakyReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+        failures: Vec<usize>,
+    }
+
+    impl<'a> Read for FlakyReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.failures.first() == Some(&self.pos) {
+                self.failures.remove(0);
+                return Err(io::Error::other("flaky"));
+            }
+            let n = cmp::min(cmp::min(buf.len(), 100), self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..(self.pos + n)]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_parallel_decoder_retries_after_io_errors() {
+        let data = sample_data(20000);
+        let opts = Lz77Options {
+            block_size: Some(1000),
+            block_index: true,
+            ..Default::default()
+        };
+        let compressed = compress(&data, &opts);
+        let reader = FlakyReader {
+            data: &compressed,
+            pos: 0,
+            // In the middle of blocks, and in the index of 336 bytes in front of the trailer.
+            failures: vec![0, 500, 3000, (compressed.len() - 200) / 100 * 100],
+        };
+        let mut decoder = ParallelLz77Decoder::new(reader, opts, 2);
+        let mut output = Vec::new();
+        let mut errors = 0;
+        let mut buf = [0; 700];
+        loop {
+            match decoder.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!(errors, 4);
+        assert_eq!(output, data);
+    }
+}
+
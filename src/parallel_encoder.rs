@@ -0,0 +1,295 @@
+use std::cmp;
+use std::io;
+use std::mem;
+use ::Lz77Options;
+use header::{Header, HEADER_SIZE};
+use frame::{BlockHeader, BlockIndex, END_OF_BLOCKS};
+use checksum::Crc32;
+use oneshot::compress_block;
+use pool::OrderedPool;
+
+const INNER_TAKEN: &str = "Inner writer already taken";
+/// Block size used if the options do not set one.
+pub const DEFAULT_PARALLEL_BLOCK_SIZE: usize = 1 << 20;
+
+/// Encodes everything written to it into `W`, compressing blocks of `block_size` bytes on a
+/// pool of worker threads. The blocks are written in order, so the stream can be read by an
+/// `Lz77Decoder` like any other stream split into blocks.
+///
+/// If `linked_blocks` is set, every block is primed with the tail of the previous block, as much
+/// of it as fits into the window, and may reference it. Otherwise blocks are independent.
+///
+/// At most two blocks per thread are held in memory, compressed or not.
+pub struct ParallelLz77Encoder<W>
+    where W: io::Write
+{
+    out: Option<W>,
+    options: Lz77Options,
+    block_size: usize,
+    max_pending: usize,
+    pool: OrderedPool<(Vec<u8>, usize), Vec<u8>>,
+    /// Input of the block being collected, behind `primer` bytes of the previous block.
+    block: Vec<u8>,
+    primer: usize,
+    checksum: Option<Crc32>,
+    header_written: bool,
+    /// Index of the blocks written so far and the stream offset of the next block, if the
+    /// stream ends with an index.
+    index: Option<BlockIndex>,
+    block_offset: u64,
+}
+
+impl<W> ParallelLz77Encoder<W>
+    where W: io::Write
+{
+    /// Creates an encoder compressing on `threads` worker threads, or on one per available CPU
+    /// if `threads` is 0. If `options` do not set a `block_size`, `DEFAULT_PARALLEL_BLOCK_SIZE`
+    /// is used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `options` are invalid, see `Lz77Options::validate`, or ask for a raw stream,
+    /// which can not be split into blocks.
+    pub fn new(output: W, mut options: Lz77Options, threads: usize) -> Self {
+        if let Err(e) = options.validate() {
+            panic!("{}", e);
+        }
+        assert!(!options.raw, "Raw streams can not be split into blocks");
+        let block_size = *options.block_size.get_or_insert(DEFAULT_PARALLEL_BLOCK_SIZE);
+        let block_options = options.clone();
+        let pool = OrderedPool::new(threads, move |(input, start): (Vec<u8>, usize)| {
+            compress_block(&input, start, &block_options)
+        });
+        ParallelLz77Encoder {
+            out: Some(output),
+            block_size,
+            max_pending: 2 * pool.threads(),
+            pool,
+            block: Vec::with_capacity(block_size),
+            primer: 0,
+            checksum: if options.checksum { Some(Crc32::new()) } else { None },
+            header_written: false,
+            index: if options.block_index { Some(BlockIndex::default()) } else { None },
+            block_offset: HEADER_SIZE as u64,
+            options,
+        }
+    }
+
+    /// Writes all pending blocks and the stream trailer, then returns the inner writer.
+    ///
+    /// Unlike dropping the encoder, this reports errors that occur while writing the final
+    /// blocks.
+    pub fn finish(mut self) -> io::Result<W> {
+        let res = self.finish_stream();
+        // Taking the writer keeps `Drop` from retrying after an error.
+        let out = self.out.take().expect(INNER_TAKEN);
+        res.map(|_| out)
+    }
+
+    /// Returns the inner writer without writing pending blocks or the stream trailer.
+    pub fn into_inner(mut self) -> W {
+        self.out.take().expect(INNER_TAKEN)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.out.as_ref().expect(INNER_TAKEN)
+    }
+
+    /// Writing to the inner writer directly will corrupt the compressed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.out.as_mut().expect(INNER_TAKEN)
+    }
+
+    /// Writes `buf` to the inner writer, behind the stream header if it was not written yet.
+    fn write_output(&mut self, buf: &[u8]) -> io::Result<()> {
+        let out = self.out.as_mut().expect(INNER_TAKEN);
+        if !self.header_written {
+            out.write_all(&Header::from_options(&self.options).as_bytes()[..])?;
+            self.header_written = true;
+        }
+        out.write_all(buf)
+    }
+
+    /// Waits for the oldest pending block and writes it.
+    fn write_next_block(&mut self) -> io::Result<()> {
+        if let Some(compressed) = self.pool.next_result() {
+            if let Some(ref mut index) = self.index {
+                let header = BlockHeader::parse(&compressed, self.options.block_checksum);
+                index.push(self.block_offset, header.uncompressed_size);
+            }
+            self.block_offset += compressed.len() as u64;
+            self.write_output(&compressed)?;
+        }
+        Ok(())
+    }
+
+    /// Hands the collected block to the pool, writing finished blocks first if too many are
+    /// pending.
+    fn submit_block(&mut self) -> io::Result<()> {
+        if self.block.len() == self.primer {
+            return Ok(());
+        }
+        while self.pool.pending() >= self.max_pending {
+            self.write_next_block()?;
+        }
+        let mut next = Vec::with_capacity(self.block_size);
+        let mut primer = 0;
+        if self.options.linked_blocks {
+            let window_capacity = (1 << self.options.window_bits as usize) - 1;
+            let tail = &self.block[self.block.len().saturating_sub(window_capacity)..];
+            next.extend_from_slice(tail);
+            primer = tail.len();
+        }
+        let block = mem::replace(&mut self.block, next);
+        self.pool.submit((block, self.primer));
+        self.primer = primer;
+        Ok(())
+    }
+
+    /// Compresses and writes everything written so far, ending the current block.
+    fn write_pending(&mut self) -> io::Result<()> {
+        self.submit_block()?;
+        while self.pool.pending() > 0 {
+            self.write_next_block()?;
+        }
+        Ok(())
+    }
+
+    fn finish_stream(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.write_output(&END_OF_BLOCKS)?;
+        if let Some(index) = self.index.take() {
+            let mut index_bytes = Vec::with_capacity(BlockIndex::size(index.entries.len()));
+            index.write(&mut index_bytes);
+            self.write_output(&index_bytes)?;
+        }
+        if let Some(crc) = self.checksum.take() {
+            self.write_output(&crc.sum().to_be_bytes())?;
+        }
+        self.get_mut().flush()
+    }
+}
+
+impl<W> io::Write for ParallelLz77Encoder<W>
+    where W: io::Write
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(ref mut crc) = self.checksum {
+            crc.update(buf);
+        }
+        let mut input = buf;
+        while !input.is_empty() {
+            let space = self.block_size - (self.block.len() - self.primer);
+            let n = cmp::min(space, input.len());
+            self.block.extend_from_slice(&input[..n]);
+            input = &input[n..];
+            if n == space {
+                self.submit_block()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Ends the current block early and writes all blocks compressed so far.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W> Drop for ParallelLz77Encoder<W>
+    where W: io::Write
+{
+    fn drop(&mut self) {
+        if self.out.is_some() {
+            let _ = self.finish_stream();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParallelLz77Encoder;
+    use std::io::Write;
+    use test_util::sample_data;
+    use ::{compress, decompress, Lz77Decoder, Lz77Options, SearcherKind};
+
+    fn encode(data: &[u8], opts: &Lz77Options, threads: usize) -> Vec<u8> {
+        let mut encoder = ParallelLz77Encoder::new(Vec::new(), opts.clone(), threads);
+        for chunk in data.chunks(777) {
+            encoder.write_all(chunk).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_parallel_encoder() {
+        let data = sample_data(50000);
+        for &linked in &[false, true] {
+            for &huffman in &[false, true] {
+                let opts = Lz77Options {
+                    block_size: Some(4000),
+                    linked_blocks: linked,
+                    block_checksum: true,
+                    block_index: !linked,
+                    checksum: true,
+                    huffman,
+                    ..Default::default()
+                };
+                let expected = encode(&data, &opts, 1);
+                for &threads in &[2, 3, 8] {
+                    assert_eq!(encode(&data, &opts, threads), expected);
+                }
+                if !linked {
+                    assert_eq!(expected, compress(&data, &opts));
+                }
+                assert_eq!(decompress(&expected, &opts).unwrap(), data);
+                let mut decoded = Vec::new();
+                let mut decoder = Lz77Decoder::new(&expected[..], opts.clone());
+                ::std::io::copy(&mut decoder, &mut decoded).unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_primed_blocks_are_smaller() {
+        let data = sample_data(50000);
+        let independent = Lz77Options { block_size: Some(1000), ..Default::default() };
+        let linked = Lz77Options { linked_blocks: true, ..independent.clone() };
+        let primed = encode(&data, &linked, 4);
+        assert!(primed.len() < encode(&data, &independent, 4).len());
+        assert_eq!(decompress(&primed, &linked).unwrap(), data);
+    }
+
+    #[test]
+    fn test_suffix_array_blocks() {
+        let data = sample_data(50000);
+        for &linked in &[false, true] {
+            let opts = Lz77Options {
+                block_size: Some(4000),
+                linked_blocks: linked,
+                searcher: SearcherKind::SuffixArray,
+                ..Default::default()
+            };
+            let compressed = encode(&data, &opts, 3);
+            assert_eq!(compressed, encode(&data, &opts, 1));
+            assert_eq!(decompress(&compressed, &opts).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_parallel_encoder_flush() {
+        let data = sample_data(10000);
+        let opts = Lz77Options::default();
+        let mut encoder = ParallelLz77Encoder::new(Vec::new(), opts.clone(), 2);
+        encoder.write_all(&data[..3000]).unwrap();
+        encoder.flush().unwrap();
+        assert!(!encoder.get_ref().is_empty());
+        encoder.write_all(&data[3000..]).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress(&compressed, &opts).unwrap(), data);
+        let empty = ParallelLz77Encoder::new(Vec::new(), opts.clone(), 2).finish().unwrap();
+        assert_eq!(decompress(&empty, &opts).unwrap(), b"");
+    }
+}
@@ -0,0 +1,140 @@
+use std::io;
+use ::Lz77Options;
+use searcher::SearchResult;
+use token::Token;
+use optimal::{self, Block};
+
+/// A match found at the current position, kept back while lazy matching checks the
+/// following positions for a better one.
+pub struct PendingMatch {
+    dist: usize,
+    len: usize,
+    next: u8,
+    /// Bytes of the match already moved into the window while looking ahead.
+    skipped: usize,
+}
+
+/// The view of an encoder that the parser needs: a window of already encoded bytes followed by
+/// a lookahead of bytes still to be encoded.
+pub trait ParseState {
+    fn options(&self) -> &Lz77Options;
+
+    fn pending(&mut self) -> &mut Option<PendingMatch>;
+
+    /// Searches the window for the longest match of the first `key_len` lookahead bytes.
+    /// Positions are relative to the start of the window.
+    fn search(&mut self, key_len: usize) -> Option<SearchResult>;
+
+    /// Like `search`, but adds every candidate match the searcher reports to `matches`.
+    fn search_all(&mut self, key_len: usize, matches: &mut Vec<SearchResult>);
+
+    /// The block collected by the optimal parser.
+    fn block(&mut self) -> &mut Option<Block>;
+
+    fn window_len(&self) -> usize;
+
+    fn window_byte(&self, idx: usize) -> u8;
+
+    fn lookahead_byte(&self, idx: usize) -> u8;
+
+    /// Moves `n` bytes from the lookahead into the window.
+    fn advance(&mut self, n: usize);
+
+    fn emit(&mut self, token: Token, next: u8) -> io::Result<()>;
+
+    /// Returns whether a literal run is open, so that another literal only costs its own byte.
+    fn literal_run_open(&self) -> bool;
+}
+
+fn search<P>(state: &mut P, key_len: usize) -> Option<PendingMatch>
+    where P: ParseState
+{
+    let min_match_len = state.options().match_len_floor();
+    state.search(key_len).filter(|res| res.length >= min_match_len).map(|res| {
+        PendingMatch {
+            dist: state.window_len() - res.position,
+            len: res.length,
+            next: state.lookahead_byte(res.length),
+            skipped: 0,
+        }
+    })
+}
+
+/// Encodes at least one byte of the lookahead, searching for matches of up to `key_len` bytes.
+///
+/// With lazy matching a match is not emitted right away. Instead its first bytes are moved
+/// into the window one at a time, and if a sufficiently longer match starts at one of the
+/// next `options.lazy` positions, the skipped bytes are emitted as literals in favour of it.
+///
+/// With optimal parsing the byte is only moved into the window, its tokens are emitted once
+/// its block is full or by `finish`.
+pub fn step<P>(state: &mut P, key_len: usize) -> io::Result<()>
+    where P: ParseState
+{
+    if state.options().optimal {
+        return optimal::step(state, key_len);
+    }
+    let found = search(state, key_len);
+    let candidate = match state.pending().take() {
+        Some(mut pending) => {
+            // The longer match has to make up for the skipped bytes that are emitted as
+            // literals instead: one byte each, plus a codeword and a literal to open a new
+            // literal run.
+            let literal_cost = pending.skipped + if state.literal_run_open() { 0 } else { 3 };
+            let better = match found {
+                Some(ref m) => m.len >= pending.len + literal_cost,
+                None => false,
+            };
+            if !better {
+                if pending.skipped < state.options().lazy as usize {
+                    pending.skipped += 1;
+                    *state.pending() = Some(pending);
+                    state.advance(1);
+                } else {
+                    let fw = pending.len + 1 - pending.skipped;
+                    state.emit(Token::Match(pending.dist, pending.len), pending.next)?;
+                    state.advance(fw);
+                }
+                return Ok(());
+            }
+            let window_len = state.window_len();
+            for i in (window_len - pending.skipped)..window_len {
+                let literal = state.window_byte(i);
+                state.emit(Token::Literal, literal)?;
+            }
+            found
+        }
+        None => found,
+    };
+
+    match candidate {
+        Some(m) => {
+            let lazy = {
+                let options = state.options();
+                options.lazy > 0 && m.len < key_len && m.len < options.good_match_len
+            };
+            if lazy {
+                *state.pending() = Some(PendingMatch { skipped: 1, ..m });
+                state.advance(1);
+            } else {
+                let fw = m.len + 1;
+                state.emit(Token::Match(m.dist, m.len), m.next)?;
+                state.advance(fw);
+            }
+        }
+        None => {
+            let next = state.lookahead_byte(0);
+            state.emit(Token::Literal, next)?;
+            state.advance(1);
+        }
+    }
+    Ok(())
+}
+
+/// Emits the tokens for all bytes moved into the window. Called before flushing the token
+/// writer.
+pub fn finish<P>(state: &mut P) -> io::Result<()>
+    where P: ParseState
+{
+    optimal::finish(state)
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A fixed set of worker threads running the same function on every submitted job. Results are
+/// handed out in submission order, however the jobs finish.
+pub struct OrderedPool<T, R> {
+    jobs: Option<Sender<(usize, T)>>,
+    results: Receiver<(usize, R)>,
+    workers: Vec<JoinHandle<()>>,
+    submitted: usize,
+    next: usize,
+    /// Results that finished before the one returned next.
+    finished: BTreeMap<usize, R>,
+}
+
+impl<T, R> OrderedPool<T, R>
+    where T: Send + 'static,
+          R: Send + 'static
+{
+    /// Starts `threads` workers, or one per available CPU if `threads` is 0, each running `f`
+    /// on the jobs it receives.
+    pub fn new<F>(threads: usize, f: F) -> Self
+        where F: Fn(T) -> R + Send + Sync + 'static
+    {
+        let threads = match threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n,
+        };
+        let (jobs, job_receiver) = mpsc::channel::<(usize, T)>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let f = Arc::new(f);
+        let workers = (0..threads)
+            .map(|_| {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                let f = f.clone();
+                thread::spawn(move || loop {
+                    // The lock is released before running the job.
+                    let job = job_receiver.lock().map(|receiver| receiver.recv());
+                    match job {
+                        Ok(Ok((idx, job))) => {
+                            if result_sender.send((idx, f(job))).is_err() {
+                                return;
+                            }
+                        }
+                        _ => return,
+                    }
+                })
+            })
+            .collect();
+        OrderedPool {
+            jobs: Some(jobs),
+            results,
+            workers,
+            submitted: 0,
+            next: 0,
+            finished: BTreeMap::new(),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Returns the number of jobs whose results were not handed out yet.
+    pub fn pending(&self) -> usize {
+        self.submitted - self.next
+    }
+
+    pub fn submit(&mut self, job: T) {
+        let jobs = self.jobs.as_ref().expect("Pool is shut down");
+        jobs.send((self.submitted, job)).expect("All pool workers stopped");
+        self.submitted += 1;
+    }
+
+    /// Waits for the result of the oldest pending job. Returns `None` if there is none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a worker panicked while running a job.
+    pub fn next_result(&mut self) -> Option<R> {
+        if self.pending() == 0 {
+            return None;
+        }
+        while !self.finished.contains_key(&self.next) {
+            let (idx, result) = self.results.recv().expect("A pool worker panicked");
+            self.finished.insert(idx, result);
+        }
+        let result = self.finished.remove(&self.next);
+        self.next += 1;
+        result
+    }
+}
+
+impl<T, R> Drop for OrderedPool<T, R> {
+    fn drop(&mut self) {
+        // Closing the job channel stops the workers once they are done with their current job.
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderedPool;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_results_in_order() {
+        let mut pool = OrderedPool::new(4, |n: u64| {
+            // Later jobs finish first.
+            thread::sleep(Duration::from_millis(20 - n));
+            n * n
+        });
+        for n in 0..10 {
+            pool.submit(n);
+        }
+        assert_eq!(pool.pending(), 10);
+        let results: Vec<_> = (0..10).map(|_| pool.next_result().unwrap()).collect();
+        assert_eq!(results, (0..10).map(|n| n * n).collect::<Vec<_>>());
+        assert_eq!(pool.next_result(), None);
+    }
+}
@@ -0,0 +1,316 @@
+use std::cmp;
+use std::ops::Index;
+use super::{Searcher, SearchResult};
+use ::buffer::SizedBuffer;
+use ::Lz77Options;
+
+const MIN_MATCH: usize = 2;
+const ROOTS: usize = 1 << 16;
+// Enough nodes for a window of up to 15 bits. Larger windows grow the tree in `configure`.
+const TREE_SIZE: usize = 1 << 15;
+
+/// Searcher keeping a binary search tree of all window positions, like the `bt` match finders
+/// of LZMA.
+///
+/// Every pair of leading bytes has its own tree, which orders the positions starting with them
+/// by the bytes that follow. The longest match for a key shares the most bytes with one of its
+/// neighbours in that order, and both lie on the path from the root to where the key belongs.
+/// Walking that path therefore finds the longest match in the window, and the key is inserted
+/// as the new root on the way.
+///
+/// Positions are tracked as absolute stream offsets and stored as `position + 1`, so that `0`
+/// can mark an empty slot. `tree` holds a pair of child links for each position, positions in
+/// the left subtree sort before it. A child is always older than its parent.
+pub struct BinaryTreeSearcher {
+    min_match_len: usize,
+    max_match_len: usize,
+    roots: Vec<usize>,
+    tree: Vec<usize>,
+    /// Next position to insert into the tree.
+    inserted: usize,
+    window_start: usize,
+    window_end: usize,
+}
+
+impl BinaryTreeSearcher {
+    pub fn new() -> Self {
+        BinaryTreeSearcher {
+            min_match_len: MIN_MATCH,
+            max_match_len: usize::MAX,
+            roots: vec![0; ROOTS],
+            tree: vec![0; 2 * TREE_SIZE],
+            inserted: 0,
+            window_start: 0,
+            window_end: 0,
+        }
+    }
+
+    fn search<B>(&mut self,
+                 buf: &B,
+                 key: &[u8],
+                 matches: Option<&mut Vec<SearchResult>>)
+                 -> Option<SearchResult>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        self.insert_pending(buf);
+        let pos = self.window_end;
+        if self.inserted != pos || key.len() < MIN_MATCH {
+            return None;
+        }
+        let limit = cmp::min(key.len(), self.max_match_len);
+        self.insert_position(buf, pos, limit, matches)
+    }
+
+    fn slot(&self, pos: usize) -> usize {
+        2 * (pos & (self.tree.len() / 2 - 1))
+    }
+
+    /// Inserts the window positions moved over since the last search, which reach into the
+    /// lookahead at the end of `buf`.
+    fn insert_pending<B>(&mut self, buf: &B)
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        if self.inserted < self.window_start {
+            self.inserted = self.window_start;
+        }
+        while self.inserted < self.window_end {
+            let pos = self.inserted;
+            let limit = cmp::min(buf.len() - (pos - self.window_start), self.max_match_len);
+            if limit >= MIN_MATCH {
+                self.insert_position(buf, pos, limit, None);
+            } else {
+                self.inserted += 1;
+            }
+        }
+    }
+
+    /// Makes `pos` the root of its tree, comparing up to `limit` bytes, and returns the longest
+    /// match. Every match longer than the previous ones is added to `matches`.
+    fn insert_position<B>(&mut self,
+                          buf: &B,
+                          pos: usize,
+                          limit: usize,
+                          mut matches: Option<&mut Vec<SearchResult>>)
+                          -> Option<SearchResult>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        let idx = pos - self.window_start;
+        let root = ((buf[idx] as usize) << 8) | buf[idx + 1] as usize;
+        let mut candidate = self.roots[root];
+        self.roots[root] = pos + 1;
+        self.inserted = pos + 1;
+
+        // The links still to be set: the one for positions sorting before `pos`, and the one
+        // for positions sorting after it.
+        let slot = self.slot(pos);
+        let mut left = slot;
+        let mut right = slot + 1;
+        // Bytes that all positions in the left and right subtree share with `pos`.
+        let mut left_len = 0;
+        let mut right_len = 0;
+        let mut best: Option<SearchResult> = None;
+        let mut newer = pos + 1;
+        loop {
+            // Children older than the window, or not older than their parent because their
+            // slot was reused, end the tree.
+            if candidate <= self.window_start || candidate >= newer {
+                self.tree[left] = 0;
+                self.tree[right] = 0;
+                return best;
+            }
+            newer = candidate;
+            let cand_idx = candidate - 1 - self.window_start;
+            let cand_slot = self.slot(candidate - 1);
+            let mut len = cmp::min(left_len, right_len);
+            while len < limit && buf[cand_idx + len] == buf[idx + len] {
+                len += 1;
+            }
+            if len > best.as_ref().map_or(self.min_match_len - 1, |b| b.length) {
+                let res = SearchResult {
+                    position: cand_idx,
+                    length: len,
+                };
+                if let Some(ref mut matches) = matches {
+                    matches.push(res);
+                }
+                best = Some(res);
+            }
+            if len == limit {
+                // The candidate can not be told apart from `pos`, which takes over its children.
+                self.tree[left] = self.tree[cand_slot];
+                self.tree[right] = self.tree[cand_slot + 1];
+                return best;
+            }
+            // Continue with the children that sort on the side of `pos`.
+            if buf[cand_idx + len] < buf[idx + len] {
+                self.tree[left] = candidate;
+                left = cand_slot + 1;
+                left_len = len;
+                candidate = self.tree[left];
+            } else {
+                self.tree[right] = candidate;
+                right = cand_slot;
+                right_len = len;
+                candidate = self.tree[right];
+            }
+        }
+    }
+}
+
+impl Default for BinaryTreeSearcher {
+    fn default() -> Self {
+        BinaryTreeSearcher::new()
+    }
+}
+
+impl Searcher for BinaryTreeSearcher {
+    fn find_longest_match<B>(&mut self, buf: &B, key: &[u8]) -> Option<SearchResult>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        self.search(buf, key, None)
+    }
+
+    /// Collects every match that is longer than the ones found before it while walking the
+    /// tree.
+    fn find_matches<B>(&mut self, buf: &B, key: &[u8], matches: &mut Vec<SearchResult>)
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        self.search(buf, key, Some(matches));
+    }
+
+    fn insert(&mut self, pos: usize, bytes: &[u8]) {
+        debug_assert_eq!(pos, self.window_end);
+        self.window_end = pos + bytes.len();
+    }
+
+    fn evict(&mut self, upto: usize) {
+        self.window_start = upto;
+    }
+
+    fn reset(&mut self) {
+        for slot in self.roots.iter_mut().chain(self.tree.iter_mut()) {
+            *slot = 0;
+        }
+        self.inserted = 0;
+        self.window_start = 0;
+        self.window_end = 0;
+    }
+
+    fn configure(&mut self, options: &Lz77Options) {
+        self.min_match_len = cmp::max(options.match_len_floor(), MIN_MATCH);
+        self.max_match_len = options.match_len_limit();
+        let tree_size = cmp::max(TREE_SIZE, 1 << options.window_bits as usize);
+        if self.tree.len() != 2 * tree_size {
+            self.tree = vec![0; 2 * tree_size];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BinaryTreeSearcher;
+    use ::buffer::{RingBuffer, CombinedBuffer, SizedBuffer};
+    use ::searcher::{SearchResult, Searcher};
+    use ::Lz77Options;
+
+    #[test]
+    fn test_binary_tree_search() {
+        let mut buffer = RingBuffer::new(16);
+        for &b in b"abcxabcdyabcz" {
+            buffer.push(b);
+        }
+
+        let mut searcher = BinaryTreeSearcher::default();
+        searcher.insert(0, b"abcxabcdyabcz");
+        let key = b"abcd".to_vec();
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res,
+                   Some(SearchResult {
+                       position: 4,
+                       length: 4,
+                   }));
+    }
+
+    #[test]
+    fn test_binary_tree_find_matches() {
+        let mut buffer = RingBuffer::new(32);
+        for &b in b"abcdeXabcYabZab" {
+            buffer.push(b);
+        }
+
+        let mut searcher = BinaryTreeSearcher::default();
+        searcher.insert(0, b"abcdeXabcYabZab");
+        let key = b"abcde".to_vec();
+        let mut matches = Vec::new();
+        searcher.find_matches(&CombinedBuffer(&buffer, &key), &key, &mut matches);
+        assert_eq!(matches.last(),
+                   Some(&SearchResult {
+                       position: 0,
+                       length: 5,
+                   }));
+        for pair in matches.windows(2) {
+            assert!(pair[0].length < pair[1].length);
+            assert!(pair[0].position > pair[1].position);
+        }
+    }
+
+    #[test]
+    fn test_binary_tree_finds_longest() {
+        let mut state: u32 = 7;
+        let data: Vec<u8> = (0..5000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"abc"[(state >> 16) as usize % 3]
+            })
+            .collect();
+        let key_len = 12;
+        let mut buffer = RingBuffer::new(511);
+        let mut searcher = BinaryTreeSearcher::default();
+        searcher.configure(&Lz77Options {
+            window_bits: 9,
+            min_match_len: Some(2),
+            ..Default::default()
+        });
+        let mut pos = 0;
+        while pos + key_len < data.len() {
+            let key = &data[pos..(pos + key_len + 1)];
+            let res = searcher.find_longest_match(&CombinedBuffer(&buffer, key), &key[..key_len]);
+
+            let mut longest = 0;
+            for start in 0..buffer.len() {
+                let mut len = 0;
+                while len < key_len && CombinedBuffer(&buffer, key)[start + len] == key[len] {
+                    len += 1;
+                }
+                longest = ::std::cmp::max(longest, len);
+            }
+            assert_eq!(res.map_or(0, |r| r.length), if longest > 1 { longest } else { 0 });
+
+            // Move over a varying number of positions, like an encoder emitting matches.
+            let n = 1 + pos % 5;
+            for &b in &data[pos..(pos + n)] {
+                buffer.push(b);
+            }
+            searcher.insert(pos, &data[pos..(pos + n)]);
+            pos += n;
+            searcher.evict(pos - buffer.len());
+        }
+    }
+
+    #[test]
+    fn test_binary_tree_respects_eviction() {
+        let mut buffer = RingBuffer::new(4);
+        let mut searcher = BinaryTreeSearcher::default();
+        for (pos, &b) in b"abcdefgh".iter().enumerate() {
+            let key = b"abcdefgh"[pos..].to_vec();
+            searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+            buffer.push(b);
+            searcher.insert(pos, &[b]);
+            searcher.evict(pos + 1 - buffer.len());
+        }
+
+        let key = b"abc".to_vec();
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res, None);
+    }
+}
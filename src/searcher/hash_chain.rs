@@ -0,0 +1,318 @@
+use std::cmp;
+use std::ops::Index;
+use super::{Searcher, SearchResult};
+use ::buffer::SizedBuffer;
+use ::Lz77Options;
+
+const MIN_MATCH: usize = 3;
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+// Chain links for 16 bits of positions never alias inside a window of up to 15 bits. Larger
+// windows grow the chain table in `configure`.
+const CHAIN_SIZE: usize = 1 << 16;
+
+pub const DEFAULT_MAX_CHAIN: usize = 64;
+
+/// Searcher keeping hash chains over the first three bytes of every window position.
+///
+/// Positions are tracked as absolute stream offsets. `head` holds the most recent position for
+/// each hash, `prev` links every position to the previous one with the same hash. Both store
+/// `position + 1` so that `0` can mark an empty slot.
+///
+/// Positions are hashed as soon as `insert` provides their three bytes. The last two window
+/// positions also need bytes of the lookahead, so they are hashed by `find_longest_match`.
+/// Matches shorter than three bytes are never found, even with a smaller `min_match_len`.
+pub struct HashChainSearcher {
+    max_chain: usize,
+    min_match_len: usize,
+    good_match_len: usize,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    /// Next position to hash.
+    inserted: usize,
+    /// The window bytes from `inserted` on, at most two.
+    tail: [u8; MIN_MATCH - 1],
+    window_start: usize,
+    window_end: usize,
+}
+
+impl HashChainSearcher {
+    pub fn new(max_chain: usize) -> Self {
+        HashChainSearcher {
+            max_chain,
+            min_match_len: MIN_MATCH,
+            good_match_len: usize::MAX,
+            head: vec![0; HASH_SIZE],
+            prev: vec![0; CHAIN_SIZE],
+            inserted: 0,
+            tail: [0; MIN_MATCH - 1],
+            window_start: 0,
+            window_end: 0,
+        }
+    }
+
+    fn hash(a: u8, b: u8, c: u8) -> usize {
+        (((a as usize) << 10) ^ ((b as usize) << 5) ^ (c as usize)) & (HASH_SIZE - 1)
+    }
+
+    fn link(&mut self, h: usize) {
+        let mask = self.prev.len() - 1;
+        self.prev[self.inserted & mask] = self.head[h];
+        self.head[h] = self.inserted + 1;
+        self.inserted += 1;
+    }
+
+    /// Hashes the last window positions, whose bytes extend into the lookahead.
+    fn insert_tail<B>(&mut self, buf: &B)
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        while self.inserted < self.window_end {
+            let idx = self.inserted - self.window_start;
+            if idx + MIN_MATCH > buf.len() {
+                break;
+            }
+            let h = Self::hash(buf[idx], buf[idx + 1], buf[idx + 2]);
+            self.link(h);
+        }
+        let tail_len = self.window_end - self.inserted;
+        for i in 0..tail_len {
+            self.tail[i] = buf[self.inserted - self.window_start + i];
+        }
+    }
+}
+
+impl Default for HashChainSearcher {
+    fn default() -> Self {
+        HashChainSearcher::new(DEFAULT_MAX_CHAIN)
+    }
+}
+
+impl Searcher for HashChainSearcher {
+    fn find_longest_match<B>(&mut self, buf: &B, key: &[u8]) -> Option<SearchResult>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        self.insert_tail(buf);
+        if key.len() < self.min_match_len {
+            return None;
+        }
+
+        let mut best: Option<SearchResult> = None;
+        let mut candidate = self.head[Self::hash(key[0], key[1], key[2])];
+        let mut chain = 0;
+        while candidate > self.window_start && chain < self.max_chain {
+            let pos = candidate - 1;
+            let idx = pos - self.window_start;
+            let mut len = 0;
+            while len < key.len() && idx + len < buf.len() && buf[idx + len] == key[len] {
+                len += 1;
+            }
+            let better = match best {
+                Some(ref b) => len > b.length,
+                None => len >= self.min_match_len,
+            };
+            if better {
+                best = Some(SearchResult {
+                    position: idx,
+                    length: len,
+                });
+                if len == key.len() || len >= self.good_match_len {
+                    break;
+                }
+            }
+
+            let next = self.prev[pos & (self.prev.len() - 1)];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain += 1;
+        }
+        best
+    }
+
+    fn insert(&mut self, pos: usize, bytes: &[u8]) {
+        debug_assert_eq!(pos, self.window_end);
+        let tail_len = self.window_end - self.inserted;
+        self.window_end = pos + bytes.len();
+        let byte_at = |tail: &[u8], i: usize| if i < tail_len {
+            tail[i]
+        } else {
+            bytes[i - tail_len]
+        };
+        // `i` counts from the first position not hashed yet, which may lie before `bytes`.
+        let mut i = 0;
+        while self.inserted + MIN_MATCH <= self.window_end {
+            let h = Self::hash(byte_at(&self.tail, i),
+                               byte_at(&self.tail, i + 1),
+                               byte_at(&self.tail, i + 2));
+            self.link(h);
+            i += 1;
+        }
+        let mut tail = [0; MIN_MATCH - 1];
+        for (j, byte) in tail.iter_mut().enumerate().take(self.window_end - self.inserted) {
+            *byte = byte_at(&self.tail, i + j);
+        }
+        self.tail = tail;
+    }
+
+    fn evict(&mut self, upto: usize) {
+        self.window_start = upto;
+        // Positions evicted before their three bytes were known are never hashed.
+        if self.inserted < upto {
+            let tail_len = self.window_end - self.inserted;
+            self.tail.copy_within((upto - self.inserted)..tail_len, 0);
+            self.inserted = upto;
+        }
+    }
+
+    fn reset(&mut self) {
+        for slot in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            *slot = 0;
+        }
+        self.inserted = 0;
+        self.window_start = 0;
+        self.window_end = 0;
+    }
+
+    fn configure(&mut self, options: &Lz77Options) {
+        self.max_chain = options.max_chain;
+        self.min_match_len = cmp::max(options.match_len_floor(), MIN_MATCH);
+        self.good_match_len = options.good_match_len;
+        let chain_size = cmp::max(CHAIN_SIZE, 1 << options.window_bits as usize);
+        if self.prev.len() != chain_size {
+            self.prev = vec![0; chain_size];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashChainSearcher;
+    use ::buffer::{RingBuffer, CombinedBuffer, SizedBuffer};
+    use ::searcher::{SearchResult, Searcher};
+    use ::Lz77Options;
+
+    #[test]
+    fn test_hash_chain_search() {
+        let mut buffer = RingBuffer::new(6);
+        let data = [1, 100, 101, 200, 100, 100];
+        for &b in &data {
+            buffer.push(b);
+        }
+
+        let mut searcher = HashChainSearcher::default();
+        searcher.insert(0, &data);
+        let key = vec![1, 100, 101];
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res,
+                   Some(SearchResult {
+                       position: 0,
+                       length: 3,
+                   }));
+    }
+
+    #[test]
+    fn test_hash_chain_prefers_longest() {
+        let mut buffer = RingBuffer::new(16);
+        for &b in b"abcxabcdyabcz" {
+            buffer.push(b);
+        }
+
+        let mut searcher = HashChainSearcher::new(8);
+        searcher.insert(0, b"abcxabcdyabcz");
+        let key = b"abcd".to_vec();
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res,
+                   Some(SearchResult {
+                       position: 4,
+                       length: 4,
+                   }));
+    }
+
+    #[test]
+    fn test_hash_chain_good_match() {
+        let mut buffer = RingBuffer::new(16);
+        for &b in b"abcdxabcdefy" {
+            buffer.push(b);
+        }
+
+        let mut searcher = HashChainSearcher::default();
+        searcher.configure(&Lz77Options { good_match_len: 4, ..Default::default() });
+        searcher.insert(0, b"abcdxabcdefy");
+        let key = b"abcdef".to_vec();
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res,
+                   Some(SearchResult {
+                       position: 5,
+                       length: 6,
+                   }));
+
+        let key = b"abcdxz".to_vec();
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res,
+                   Some(SearchResult {
+                       position: 5,
+                       length: 4,
+                   }));
+    }
+
+    #[test]
+    fn test_hash_chain_respects_eviction() {
+        let mut buffer = RingBuffer::new(4);
+        let mut searcher = HashChainSearcher::default();
+        for (pos, &b) in b"abcdefgh".iter().enumerate() {
+            buffer.push(b);
+            searcher.insert(pos, &[b]);
+            searcher.evict(pos + 1 - buffer.len());
+            let key: Vec<u8> = Vec::new();
+            searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        }
+
+        let key = b"abc".to_vec();
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    fn test_hash_chain_incremental_insert() {
+        let data = b"xyzabcabcdxyzabcabcd";
+        let mut buffer = RingBuffer::new(32);
+        for &b in &data[..] {
+            buffer.push(b);
+        }
+        let key = b"abcd".to_vec();
+        let expected = Some(SearchResult {
+            position: 16,
+            length: 4,
+        });
+
+        // Positions are hashed the same no matter how the bytes are split.
+        for chunk_size in 1..5 {
+            let mut searcher = HashChainSearcher::default();
+            for (i, chunk) in data.chunks(chunk_size).enumerate() {
+                searcher.insert(i * chunk_size, chunk);
+            }
+            let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+            assert_eq!(res, expected);
+        }
+    }
+
+    #[test]
+    fn test_hash_chain_reset() {
+        let mut buffer = RingBuffer::new(16);
+        for &b in b"abcdabcd" {
+            buffer.push(b);
+        }
+        let mut searcher = HashChainSearcher::default();
+        searcher.insert(0, b"abcdabcd");
+        searcher.reset();
+
+        // Nothing inserted before the reset is found again.
+        let mut buffer = RingBuffer::new(16);
+        buffer.push(b'x');
+        searcher.insert(0, b"x");
+        let key = b"abcd".to_vec();
+        let res = searcher.find_longest_match(&CombinedBuffer(&buffer, &key), &key);
+        assert_eq!(res, None);
+    }
+}
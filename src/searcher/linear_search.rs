@@ -1,19 +1,23 @@
 use std::ops::Index;
-use std::default::Default;
 use super::{Searcher, SearchResult};
 use ::buffer::SizedBuffer;
+use ::Lz77Options;
 
-pub struct LinearSearcher {}
+const MIN_MATCH: usize = 2;
+
+pub struct LinearSearcher {
+    min_match_len: usize,
+}
 
 impl Default for LinearSearcher {
     fn default() -> Self {
-        LinearSearcher {}
+        LinearSearcher { min_match_len: MIN_MATCH }
     }
 }
 
 impl Searcher for LinearSearcher {
     fn find_longest_match<B>(&mut self, buf: &B, key: &[u8]) -> Option<SearchResult>
-        where B: SizedBuffer + Index<usize, Output = u8>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
     {
         let mut best: Option<SearchResult> = None;
         if buf.len() > (key.len() + 2) {
@@ -31,7 +35,7 @@ impl Searcher for LinearSearcher {
                             length: len,
                         })
                     }
-                    (None, len) if len > 1 => {
+                    (None, len) if len >= self.min_match_len => {
                         Some(SearchResult {
                             position: i,
                             length: len,
@@ -44,6 +48,10 @@ impl Searcher for LinearSearcher {
 
         best
     }
+
+    fn configure(&mut self, options: &Lz77Options) {
+        self.min_match_len = options.match_len_floor();
+    }
 }
 
 #[cfg(test)]
@@ -62,7 +70,7 @@ mod tests {
         buffer.push(100);
         buffer.push(100);
 
-        let mut searcher = LinearSearcher {};
+        let mut searcher = LinearSearcher::default();
         let key = vec![1, 100, 101];
         let res = searcher.find_longest_match(&buffer, &key);
         assert!(res.is_some());
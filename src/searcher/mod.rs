@@ -1,17 +1,183 @@
 pub mod linear_search;
+pub mod hash_chain;
+pub mod binary_tree;
+pub mod suffix_array;
 
 use std::ops::Index;
 use ::buffer::SizedBuffer;
+use ::Lz77Options;
 
 pub use self::linear_search::LinearSearcher;
+pub use self::hash_chain::HashChainSearcher;
+pub use self::binary_tree::BinaryTreeSearcher;
+pub use self::suffix_array::SuffixArraySearcher;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct SearchResult {
     pub position: usize,
     pub length: usize,
 }
 
+/// Finds matches for the lookahead in the window.
+///
+/// The encoder keeps a searcher informed about the window through `insert`, `evict` and
+/// `reset`, using absolute stream offsets, so that stateful searchers can maintain an index
+/// instead of scanning the window on every call. Stateless searchers can ignore these hooks.
 pub trait Searcher {
+    /// Returns the longest match for `key`, the start of the lookahead. `buf` holds the window
+    /// followed by the lookahead, the returned position is an index into `buf`.
     fn find_longest_match<B>(&mut self, buf: &B, key: &[u8]) -> Option<SearchResult>
-        where B: SizedBuffer + Index<usize, Output = u8>;
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized;
+
+    /// Like `find_longest_match`, but adds candidate matches to `matches` for parsers that
+    /// weigh several of them. Their lengths increase, so the last one is the longest. The
+    /// default only adds the longest match.
+    fn find_matches<B>(&mut self, buf: &B, key: &[u8], matches: &mut Vec<SearchResult>)
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        if let Some(res) = self.find_longest_match(buf, key) {
+            matches.push(res);
+        }
+    }
+
+    /// Called after `bytes`, starting at stream offset `pos`, were moved from the lookahead
+    /// into the window. Consecutive calls cover the stream without gaps.
+    fn insert(&mut self, _pos: usize, _bytes: &[u8]) {}
+
+    /// Called after the window dropped every byte before stream offset `upto`.
+    fn evict(&mut self, _upto: usize) {}
+
+    /// Forgets all inserted bytes, the next `insert` starts a new stream at offset 0. Called by
+    /// the encoder before it starts using a searcher.
+    fn reset(&mut self) {}
+
+    /// Applies the tuning parameters in `options`. Called by `Lz77Encoder::new` on the default
+    /// searcher before encoding starts.
+    fn configure(&mut self, _options: &Lz77Options) {}
+}
+
+/// Available searcher implementations, selectable at runtime through `AnySearcher`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SearcherKind {
+    Linear,
+    HashChain,
+    BinaryTree,
+    /// `SuffixArraySearcher` over the whole input. Only `compress`, `compress_into` and
+    /// `ParallelLz77Encoder`, which see all of the input or of a block at once, can build it.
+    /// Encoders that get their input piece by piece use `BinaryTree` instead, which finds
+    /// matches of the same length.
+    SuffixArray,
+}
+
+/// Searcher dispatching to the implementation chosen by `Lz77Options::searcher`.
+pub enum AnySearcher {
+    Linear(LinearSearcher),
+    HashChain(HashChainSearcher),
+    BinaryTree(BinaryTreeSearcher),
+}
+
+impl AnySearcher {
+    pub fn kind(&self) -> SearcherKind {
+        match *self {
+            AnySearcher::Linear(_) => SearcherKind::Linear,
+            AnySearcher::HashChain(_) => SearcherKind::HashChain,
+            AnySearcher::BinaryTree(_) => SearcherKind::BinaryTree,
+        }
+    }
+}
+
+impl Default for AnySearcher {
+    fn default() -> Self {
+        AnySearcher::HashChain(HashChainSearcher::default())
+    }
+}
+
+impl Searcher for AnySearcher {
+    fn find_longest_match<B>(&mut self, buf: &B, key: &[u8]) -> Option<SearchResult>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        match *self {
+            AnySearcher::Linear(ref mut s) => s.find_longest_match(buf, key),
+            AnySearcher::HashChain(ref mut s) => s.find_longest_match(buf, key),
+            AnySearcher::BinaryTree(ref mut s) => s.find_longest_match(buf, key),
+        }
+    }
+
+    fn find_matches<B>(&mut self, buf: &B, key: &[u8], matches: &mut Vec<SearchResult>)
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        match *self {
+            AnySearcher::Linear(ref mut s) => s.find_matches(buf, key, matches),
+            AnySearcher::HashChain(ref mut s) => s.find_matches(buf, key, matches),
+            AnySearcher::BinaryTree(ref mut s) => s.find_matches(buf, key, matches),
+        }
+    }
+
+    fn insert(&mut self, pos: usize, bytes: &[u8]) {
+        match *self {
+            AnySearcher::Linear(ref mut s) => s.insert(pos, bytes),
+            AnySearcher::HashChain(ref mut s) => s.insert(pos, bytes),
+            AnySearcher::BinaryTree(ref mut s) => s.insert(pos, bytes),
+        }
+    }
+
+    fn evict(&mut self, upto: usize) {
+        match *self {
+            AnySearcher::Linear(ref mut s) => s.evict(upto),
+            AnySearcher::HashChain(ref mut s) => s.evict(upto),
+            AnySearcher::BinaryTree(ref mut s) => s.evict(upto),
+        }
+    }
+
+    fn reset(&mut self) {
+        match *self {
+            AnySearcher::Linear(ref mut s) => s.reset(),
+            AnySearcher::HashChain(ref mut s) => s.reset(),
+            AnySearcher::BinaryTree(ref mut s) => s.reset(),
+        }
+    }
+
+    fn configure(&mut self, options: &Lz77Options) {
+        let kind = match options.searcher {
+            SearcherKind::SuffixArray => SearcherKind::BinaryTree,
+            kind => kind,
+        };
+        if self.kind() != kind {
+            *self = match kind {
+                SearcherKind::Linear => AnySearcher::Linear(LinearSearcher::default()),
+                SearcherKind::HashChain => AnySearcher::HashChain(HashChainSearcher::default()),
+                SearcherKind::BinaryTree | SearcherKind::SuffixArray => {
+                    AnySearcher::BinaryTree(BinaryTreeSearcher::default())
+                }
+            };
+        }
+        match *self {
+            AnySearcher::Linear(ref mut s) => s.configure(options),
+            AnySearcher::HashChain(ref mut s) => s.configure(options),
+            AnySearcher::BinaryTree(ref mut s) => s.configure(options),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnySearcher, Searcher, SearcherKind};
+    use ::Lz77Options;
+
+    #[test]
+    fn test_any_searcher_configure() {
+        let mut searcher = AnySearcher::default();
+        assert_eq!(searcher.kind(), SearcherKind::HashChain);
+        searcher.configure(&Lz77Options::with_level(9));
+        assert_eq!(searcher.kind(), SearcherKind::BinaryTree);
+        searcher.configure(&Lz77Options { searcher: SearcherKind::Linear, ..Default::default() });
+        assert_eq!(searcher.kind(), SearcherKind::Linear);
+        searcher.configure(&Lz77Options::with_level(1));
+        assert_eq!(searcher.kind(), SearcherKind::HashChain);
+        searcher.configure(&Lz77Options {
+            searcher: SearcherKind::SuffixArray,
+            ..Default::default()
+        });
+        assert_eq!(searcher.kind(), SearcherKind::BinaryTree);
+    }
 }
@@ -0,0 +1,360 @@
+use std::cmp;
+use std::collections::BTreeSet;
+use std::ops::Index;
+use super::{Searcher, SearchResult};
+use ::buffer::SizedBuffer;
+use ::Lz77Options;
+
+const MIN_MATCH: usize = 2;
+/// Number of LCP entries whose minimum is kept for range queries.
+const LCP_BLOCK: usize = 32;
+
+/// Searcher for a complete input known in advance, based on its suffix array.
+///
+/// Sorting all suffixes of the input puts the ones sharing the longest prefix with a position
+/// right next to it. Of the suffixes starting in the window, the two closest to the current
+/// position in that order are therefore the longest matches before and after it, and finding
+/// them only needs an ordered set of the ranks of the window positions. The length of a match
+/// is the smallest entry of the LCP array between the two ranks, the common prefix lengths of
+/// neighbouring suffixes, so no bytes are compared while searching.
+///
+/// Building the suffix array by prefix doubling takes `O(n log n)` time and the LCP array
+/// `O(n)`. Inserting, evicting and searching a position takes `O(log w)` for a window of `w`
+/// positions, plus a range minimum query over at most `2 * LCP_BLOCK` entries.
+///
+/// Positions are absolute stream offsets, which have to be indices into the input the searcher
+/// was built for. The searcher can not be used for any other input.
+pub struct SuffixArraySearcher {
+    min_match_len: usize,
+    /// Positions of the suffixes in sorted order.
+    suffixes: Vec<usize>,
+    /// Rank of the suffix starting at every position, the inverse of `suffixes`.
+    ranks: Vec<usize>,
+    /// Common prefix length of every suffix with the one ranked before it.
+    lcp: RangeMin,
+    /// Ranks of the window positions.
+    window: BTreeSet<usize>,
+    window_start: usize,
+    window_end: usize,
+}
+
+impl SuffixArraySearcher {
+    pub fn new(input: &[u8]) -> Self {
+        let suffixes = suffix_array(input);
+        let mut ranks = vec![0; input.len()];
+        for (rank, &pos) in suffixes.iter().enumerate() {
+            ranks[pos] = rank;
+        }
+        let lcp = RangeMin::new(lcp_array(input, &suffixes, &ranks));
+        SuffixArraySearcher {
+            min_match_len: MIN_MATCH,
+            suffixes,
+            ranks,
+            lcp,
+            window: BTreeSet::new(),
+            window_start: 0,
+            window_end: 0,
+        }
+    }
+
+    /// Returns the match of `key`, whose suffix has rank `current`, with the suffix ranked
+    /// `rank`.
+    fn match_len(&self, key: &[u8], current: usize, rank: usize) -> SearchResult {
+        let (first, last) = (cmp::min(rank, current), cmp::max(rank, current));
+        let common = self.lcp.min(first + 1, last + 1) as usize;
+        SearchResult {
+            position: self.suffixes[rank] - self.window_start,
+            length: cmp::min(common, key.len()),
+        }
+    }
+
+    /// Returns the matches with the window positions sorting right before and after the
+    /// current position, the longer one last.
+    fn neighbours<B>(&self, buf: &B, key: &[u8]) -> Option<(SearchResult, SearchResult)>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        if self.window_end >= self.ranks.len() || key.len() < self.min_match_len {
+            return None;
+        }
+        debug_assert!(buf.len() >= self.window_end - self.window_start + key.len());
+        let rank = self.ranks[self.window_end];
+        let before = self.window.range(..rank).next_back().map(|&r| self.match_len(key, rank, r));
+        let after = self.window.range(rank..).next().map(|&r| self.match_len(key, rank, r));
+        let (a, b) = match (before, after) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(a), None) | (None, Some(a)) => (a, a),
+            (None, None) => return None,
+        };
+        // Of two matches of the same length, the closer one is kept.
+        if (a.length, a.position) <= (b.length, b.position) {
+            Some((a, b))
+        } else {
+            Some((b, a))
+        }
+    }
+}
+
+/// Answers minimum queries over ranges of the LCP array from a sparse table of the minima of
+/// blocks of `LCP_BLOCK` entries, scanning the partial blocks at both ends.
+struct RangeMin {
+    values: Vec<u32>,
+    /// `levels[k][b]` is the minimum of the blocks `b..(b + 2^k)`.
+    levels: Vec<Vec<u32>>,
+}
+
+impl RangeMin {
+    fn new(values: Vec<u32>) -> Self {
+        let minima: Vec<u32> = values.chunks(LCP_BLOCK)
+            .map(|block| block.iter().cloned().min().unwrap_or(0))
+            .collect();
+        let mut levels = vec![minima];
+        loop {
+            let half = 1 << (levels.len() - 1);
+            let next: Vec<u32> = {
+                let prev = &levels[levels.len() - 1];
+                if prev.len() <= half {
+                    break;
+                }
+                (0..(prev.len() - half)).map(|b| cmp::min(prev[b], prev[b + half])).collect()
+            };
+            levels.push(next);
+        }
+        RangeMin { values, levels }
+    }
+
+    /// Returns the minimum of `values[start..end]`, which must not be empty.
+    fn min(&self, start: usize, end: usize) -> u32 {
+        let scan = |range: &[u32]| range.iter().cloned().min().unwrap_or(u32::MAX);
+        let (first, last) = (start / LCP_BLOCK, (end - 1) / LCP_BLOCK);
+        if first == last {
+            return scan(&self.values[start..end]);
+        }
+        let mut min = cmp::min(scan(&self.values[start..((first + 1) * LCP_BLOCK)]),
+                               scan(&self.values[(last * LCP_BLOCK)..end]));
+        let blocks = last - (first + 1);
+        if blocks > 0 {
+            let level = (usize::BITS - 1 - blocks.leading_zeros()) as usize;
+            let table = &self.levels[level];
+            min = cmp::min(min, cmp::min(table[first + 1], table[last - (1 << level)]));
+        }
+        min
+    }
+}
+
+/// Returns the length of the common prefix of every suffix with the one ranked before it, zero
+/// for the first, using Kasai's algorithm: the next position's common prefix is at most one
+/// shorter.
+fn lcp_array(input: &[u8], suffixes: &[usize], ranks: &[usize]) -> Vec<u32> {
+    let n = input.len();
+    let mut lcp = vec![0; n];
+    let mut len = 0;
+    for pos in 0..n {
+        let rank = ranks[pos];
+        if rank == 0 {
+            len = 0;
+            continue;
+        }
+        let prev = suffixes[rank - 1];
+        while pos + len < n && prev + len < n && input[pos + len] == input[prev + len] {
+            len += 1;
+        }
+        lcp[rank] = len as u32;
+        len = len.saturating_sub(1);
+    }
+    lcp
+}
+
+/// Sorts the suffixes of `input` by prefix doubling: once the suffixes are ordered by their
+/// first `k` bytes, ordering them by pairs of ranks `k` apart sorts them by `2 * k` bytes.
+fn suffix_array(input: &[u8]) -> Vec<usize> {
+    let n = input.len();
+    let mut suffixes: Vec<usize> = (0..n).collect();
+    if n == 0 {
+        return suffixes;
+    }
+    suffixes.sort_by_key(|&pos| input[pos]);
+    let mut ranks: Vec<usize> = input.iter().map(|&b| b as usize).collect();
+    let mut next_ranks = vec![0; n];
+    let mut by_second = Vec::with_capacity(n);
+    let mut counts = Vec::new();
+    let mut k = 1;
+    loop {
+        // Order by the rank `k` bytes further first, suffixes shorter than that sort first.
+        by_second.clear();
+        by_second.extend(n.saturating_sub(k)..n);
+        by_second.extend(suffixes.iter().filter(|&&pos| pos >= k).map(|&pos| pos - k));
+
+        // A stable counting sort by the rank of the suffix itself completes the order.
+        let classes = ranks.iter().cloned().max().unwrap_or(0) + 1;
+        counts.clear();
+        counts.resize(classes + 1, 0);
+        for &pos in &by_second {
+            counts[ranks[pos] + 1] += 1;
+        }
+        for class in 0..classes {
+            counts[class + 1] += counts[class];
+        }
+        for &pos in &by_second {
+            suffixes[counts[ranks[pos]]] = pos;
+            counts[ranks[pos]] += 1;
+        }
+
+        let key = |pos: usize| (ranks[pos], if pos + k < n { ranks[pos + k] + 1 } else { 0 });
+        next_ranks[suffixes[0]] = 0;
+        for i in 1..n {
+            let step = if key(suffixes[i - 1]) == key(suffixes[i]) { 0 } else { 1 };
+            next_ranks[suffixes[i]] = next_ranks[suffixes[i - 1]] + step;
+        }
+        ::std::mem::swap(&mut ranks, &mut next_ranks);
+        if ranks[suffixes[n - 1]] == n - 1 {
+            break;
+        }
+        k *= 2;
+    }
+    suffixes
+}
+
+impl Searcher for SuffixArraySearcher {
+    fn find_longest_match<B>(&mut self, buf: &B, key: &[u8]) -> Option<SearchResult>
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        self.neighbours(buf, key)
+            .map(|(_, longest)| longest)
+            .filter(|res| res.length >= self.min_match_len)
+    }
+
+    /// Adds the matches with both neighbours in suffix order, if they differ in length.
+    fn find_matches<B>(&mut self, buf: &B, key: &[u8], matches: &mut Vec<SearchResult>)
+        where B: SizedBuffer + Index<usize, Output = u8> + ?Sized
+    {
+        if let Some((shorter, longest)) = self.neighbours(buf, key) {
+            if shorter.length >= self.min_match_len && shorter.length < longest.length {
+                matches.push(shorter);
+            }
+            if longest.length >= self.min_match_len {
+                matches.push(longest);
+            }
+        }
+    }
+
+    fn insert(&mut self, pos: usize, bytes: &[u8]) {
+        debug_assert_eq!(pos, self.window_end);
+        let end = cmp::min(pos + bytes.len(), self.ranks.len());
+        for p in pos..end {
+            self.window.insert(self.ranks[p]);
+        }
+        self.window_end = pos + bytes.len();
+    }
+
+    fn evict(&mut self, upto: usize) {
+        for p in self.window_start..cmp::min(upto, self.ranks.len()) {
+            self.window.remove(&self.ranks[p]);
+        }
+        self.window_start = cmp::max(self.window_start, upto);
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+        self.window_start = 0;
+        self.window_end = 0;
+    }
+
+    fn configure(&mut self, options: &Lz77Options) {
+        self.min_match_len = options.match_len_floor();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lcp_array, suffix_array, RangeMin, SuffixArraySearcher};
+    use ::buffer::{RingBuffer, CombinedBuffer, SizedBuffer};
+    use ::searcher::{SearchResult, Searcher};
+    use test_util::random_data;
+
+    #[test]
+    fn test_suffix_array() {
+        for input in &[&b""[..], b"a", b"banana", b"mississippi", b"aaaaaaaa", b"abcabcabx"] {
+            let mut expected: Vec<usize> = (0..input.len()).collect();
+            expected.sort_by_key(|&pos| &input[pos..]);
+            assert_eq!(suffix_array(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_lcp_array() {
+        for input in &[&b""[..], b"a", b"banana", b"mississippi", b"aaaaaaaa", b"abcabcabx"] {
+            let suffixes = suffix_array(input);
+            let mut ranks = vec![0; input.len()];
+            for (rank, &pos) in suffixes.iter().enumerate() {
+                ranks[pos] = rank;
+            }
+            let lcp = lcp_array(input, &suffixes, &ranks);
+            for rank in 1..input.len() {
+                let (a, b) = (&input[suffixes[rank - 1]..], &input[suffixes[rank]..]);
+                let common = a.iter().zip(b).take_while(|&(x, y)| x == y).count();
+                assert_eq!(lcp[rank] as usize, common);
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_min() {
+        let values: Vec<u32> = random_data(300).iter().map(|&b| b as u32 % 50).collect();
+        let range_min = RangeMin::new(values.clone());
+        for start in 0..values.len() {
+            for end in (start + 1)..values.len() {
+                assert_eq!(range_min.min(start, end), *values[start..end].iter().min().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_suffix_array_search() {
+        let input = b"abcxabcdyabcdz";
+        let mut searcher = SuffixArraySearcher::new(input);
+        searcher.insert(0, &input[..9]);
+        let res = searcher.find_longest_match(&input[..], &input[9..13]);
+        assert_eq!(res,
+                   Some(SearchResult {
+                       position: 4,
+                       length: 4,
+                   }));
+    }
+
+    #[test]
+    fn test_suffix_array_finds_longest() {
+        let mut state: u32 = 7;
+        let data: Vec<u8> = (0..5000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"abc"[(state >> 16) as usize % 3]
+            })
+            .collect();
+        let key_len = 12;
+        let mut buffer = RingBuffer::new(511);
+        let mut searcher = SuffixArraySearcher::new(&data);
+        let mut pos = 0;
+        while pos + key_len < data.len() {
+            let key = &data[pos..(pos + key_len + 1)];
+            let res = searcher.find_longest_match(&CombinedBuffer(&buffer, key), &key[..key_len]);
+
+            let mut longest = 0;
+            for start in 0..buffer.len() {
+                let mut len = 0;
+                while len < key_len && CombinedBuffer(&buffer, key)[start + len] == key[len] {
+                    len += 1;
+                }
+                longest = ::std::cmp::max(longest, len);
+            }
+            assert_eq!(res.map_or(0, |r| r.length), if longest > 1 { longest } else { 0 });
+
+            let n = 1 + pos % 5;
+            for &b in &data[pos..(pos + n)] {
+                buffer.push(b);
+            }
+            searcher.insert(pos, &data[pos..(pos + n)]);
+            pos += n;
+            searcher.evict(pos - buffer.len());
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+use ::MAX_BLOCK_SIZE;
+use header::{Header, HEADER_SIZE, FLAG_BLOCKS, FLAG_BLOCK_CHECKSUM, FLAG_BLOCK_INDEX,
+             FLAG_CHECKSUM, FLAG_LINKED_BLOCKS, FLAG_DICTIONARY, DICTIONARY_ID_SIZE};
+use frame::{decode_block, BlockHeader, BlockIndex, END_OF_BLOCKS};
+use errors::Error;
+
+/// Decodes a stream with a block index, as written with `Lz77Options::block_index`, at any
+/// content offset. Seeking only moves the position, reading decodes the block holding it, so
+/// sequential reads decode every block once.
+///
+/// The stream has to start at the position of the reader when the decoder is created and end at
+/// the end of the reader, where its index is found. Block checksums are verified as blocks are
+/// decoded, the content checksum in the trailer is not. Streams encoded with a dictionary are not
+/// supported.
+pub struct SeekableLz77Decoder<R>
+    where R: Read + Seek
+{
+    inner: R,
+    /// Position of the stream header in the inner reader.
+    start: u64,
+    /// Header of a stream of a single block, put in front of every block to decode it.
+    block_stream_header: [u8; HEADER_SIZE],
+    block_checksums: bool,
+    index: BlockIndex,
+    pos: u64,
+    /// Number and content of the block decoded last.
+    block: Option<(usize, Vec<u8>)>,
+}
+
+impl<R> SeekableLz77Decoder<R>
+    where R: Read + Seek
+{
+    /// Creates a decoder reading the stream header and block index from `reader`.
+    ///
+    /// Fails with `Error::NoBlockIndex` if the stream has no index.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let start = reader.stream_position()?;
+        let mut buf = [0; HEADER_SIZE];
+        reader.read_exact(&mut buf)?;
+        let header = Header::parse(&buf)?;
+        if !header.has_flag(FLAG_BLOCKS) || !header.has_flag(FLAG_BLOCK_INDEX) {
+            return Err(Error::NoBlockIndex.into());
+        }
+        if header.has_flag(FLAG_DICTIONARY) {
+            let mut id = [0; DICTIONARY_ID_SIZE];
+            reader.read_exact(&mut id)?;
+            return Err(Error::DictionaryMismatch {
+                    expected: Some(u32::from_be_bytes(id)),
+                    actual: None,
+                }
+                .into());
+        }
+        if header.has_flag(FLAG_LINKED_BLOCKS) {
+            return Err(Error::CorruptBlock("linked blocks can not be decoded on their own").into());
+        }
+
+        let trailer_size = if header.has_flag(FLAG_CHECKSUM) { 4 } else { 0 };
+        let end = reader.seek(SeekFrom::End(0))?;
+        // The index can not overlap the stream header or the end of blocks.
+        let space = end.saturating_sub(start + (HEADER_SIZE + END_OF_BLOCKS.len()) as u64 +
+                                       trailer_size);
+        if space < BlockIndex::size(0) as u64 {
+            return Err(Error::TruncatedStream.into());
+        }
+        reader.seek(SeekFrom::Start(end - trailer_size - 4))?;
+        let mut count = [0; 4];
+        reader.read_exact(&mut count)?;
+        let index_size = BlockIndex::size(u32::from_be_bytes(count) as usize) as u64;
+        if index_size > space {
+            return Err(Error::CorruptBlock("invalid block index").into());
+        }
+        reader.seek(SeekFrom::Start(end - trailer_size - index_size))?;
+        let mut index = vec![0; index_size as usize];
+        reader.read_exact(&mut index)?;
+        let index = BlockIndex::parse(&index)?;
+
+        let block_stream_header = Header {
+            flags: header.flags & !(FLAG_CHECKSUM | FLAG_BLOCK_INDEX),
+            ..header
+        };
+        Ok(SeekableLz77Decoder {
+            inner: reader,
+            start,
+            block_stream_header: block_stream_header.as_bytes(),
+            block_checksums: header.has_flag(FLAG_BLOCK_CHECKSUM),
+            index,
+            pos: 0,
+            block: None,
+        })
+    }
+
+    /// Returns the size of the decoded content, as recorded in the index.
+    pub fn content_size(&self) -> u64 {
+        self.index.content_size
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly does not affect the decoder, which seeks to every
+    /// block it decodes.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads and decodes block `idx`.
+    fn decode(&mut self, idx: usize) -> io::Result<Vec<u8>> {
+        let (start, end) = self.index.block_range(idx);
+        let offset = self.index.entries[idx].compressed_offset;
+        self.inner.seek(SeekFrom::Start(self.start + offset))?;
+        let header_size = BlockHeader::size(self.block_checksums);
+        let mut stream = self.block_stream_header.to_vec();
+        stream.resize(HEADER_SIZE + header_size, 0);
+        self.inner.read_exact(&mut stream[HEADER_SIZE..])?;
+        let header = BlockHeader::parse(&stream[HEADER_SIZE..], self.block_checksums);
+        if header.uncompressed_size as u64 != end - start ||
+           header.uncompressed_size > MAX_BLOCK_SIZE {
+            return Err(Error::CorruptBlock("block index does not match the blocks").into());
+        }
+        let size = header.compressed_size as u64;
+        if (&mut self.inner).take(size).read_to_end(&mut stream)? as u64 != size {
+            return Err(Error::TruncatedStream.into());
+        }
+        stream.extend_from_slice(&END_OF_BLOCKS);
+        Ok(decode_block(&stream, start, header.uncompressed_size)?)
+    }
+}
+
+impl<R> Read for SeekableLz77Decoder<R>
+    where R: Read + Seek
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let idx = match self.index.find(self.pos) {
+            Some(idx) => idx,
+            None => return Ok(0),
+        };
+        if self.block.as_ref().map(|&(decoded, _)| decoded) != Some(idx) {
+            // A block that fails to decode is not kept, so reading it again fails again.
+            self.block = None;
+            let block = self.decode(idx)?;
+            self.block = Some((idx, block));
+        }
+        let block = match self.block {
+            Some((_, ref block)) => block,
+            None => unreachable!(),
+        };
+        let offset = (self.pos - self.index.entries[idx].uncompressed_offset) as usize;
+        let n = cmp::min(buf.len(), block.len() - offset);
+        buf[..n].copy_from_slice(&block[offset..(offset + n)]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R> Seek for SeekableLz77Decoder<R>
+    where R: Read + Seek
+{
+    /// Moves the position in the decoded content. Positions past its end are allowed, reading
+    /// there returns no bytes.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.index.content_size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                   "Seek to a negative or overflowing position"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeekableLz77Decoder;
+    use std::io::{self, Cursor, Read, Seek, SeekFrom};
+    use errors::Error;
+    use test_util::sample_data;
+    use ::{compress, Lz77Options};
+
+    fn inner_error(err: io::Error) -> Option<Error> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<Error>()).cloned()
+    }
+
+    #[test]
+    fn test_random_access() {
+        let data = sample_data(50000);
+        for &(checksum, block_checksum, huffman) in &[(true, false, false),
+                                                       (false, true, true)] {
+            let opts = Lz77Options {
+                block_size: Some(3000),
+                block_index: true,
+                checksum,
+                block_checksum,
+                huffman,
+                ..Default::default()
+            };
+            // The stream does not have to start at the beginning of the reader.
+            let mut stored = b"prefix".to_vec();
+            stored.extend_from_slice(&compress(&data, &opts));
+            let mut reader = Cursor::new(stored);
+            reader.seek(SeekFrom::Start(6)).unwrap();
+            let mut decoder = SeekableLz77Decoder::new(reader).unwrap();
+            assert_eq!(decoder.content_size(), data.len() as u64);
+
+            for &(start, len) in &[(0, 10), (2999, 2), (12345, 7000), (49990, 10), (0, 50000)] {
+                assert_eq!(decoder.seek(SeekFrom::Start(start)).unwrap(), start);
+                let mut buf = vec![0; len];
+                decoder.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf[..], &data[(start as usize)..(start as usize + len)]);
+            }
+            assert_eq!(decoder.seek(SeekFrom::End(-100)).unwrap(), 49900);
+            assert_eq!(decoder.seek(SeekFrom::Current(-900)).unwrap(), 49000);
+            let mut rest = Vec::new();
+            decoder.read_to_end(&mut rest).unwrap();
+            assert_eq!(&rest[..], &data[49000..]);
+            assert_eq!(decoder.seek(SeekFrom::End(10)).unwrap(), 50010);
+            assert_eq!(decoder.read(&mut [0; 10]).unwrap(), 0);
+            assert!(decoder.seek(SeekFrom::Current(-50011)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_seekable_errors() {
+        let data = sample_data(10000);
+        let no_index = Lz77Options { block_size: Some(1000), ..Default::default() };
+        let res = SeekableLz77Decoder::new(Cursor::new(compress(&data, &no_index)));
+        assert_eq!(res.map(|_| ()).map_err(inner_error), Err(Some(Error::NoBlockIndex)));
+
+        let opts = Lz77Options { block_index: true, block_checksum: true, ..no_index };
+        let mut compressed = compress(&data, &opts);
+        // Flips a literal of the third block, which starts with a literal run.
+        let mut pos = 7;
+        for _ in 0..2 {
+            pos += 12 + u32::from_be_bytes([compressed[pos], compressed[pos + 1],
+                                            compressed[pos + 2], compressed[pos + 3]]) as usize;
+        }
+        compressed[pos + 12 + 2] ^= 0x01;
+        let mut decoder = SeekableLz77Decoder::new(Cursor::new(compressed)).unwrap();
+        let mut buf = [0; 100];
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[..100]);
+        decoder.seek(SeekFrom::Start(2500)).unwrap();
+        assert!(matches!(decoder.read(&mut buf).map_err(inner_error),
+                         Err(Some(Error::BlockChecksumMismatch { offset: 2000, .. }))));
+        decoder.seek(SeekFrom::Start(3000)).unwrap();
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[3000..3100]);
+
+        let mut truncated = compress(&data, &opts);
+        truncated.truncate(truncated.len() - 4);
+        assert!(SeekableLz77Decoder::new(Cursor::new(truncated)).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+//! Test data shared by the unit tests.
+
+/// Returns `len` bytes of a repeated pangram with about every fifth byte replaced by noise, which
+/// compresses well but leaves the searchers something to do.
+pub fn sample_data(len: usize) -> Vec<u8> {
+    let mut state: u32 = 3;
+    (0..len)
+        .map(|i| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            if (state >> 16) % 5 == 1 {
+                (state >> 24) as u8
+            } else {
+                b"sphinx of black quartz, judge my vow. "[i % 38]
+            }
+        })
+        .collect()
+}
+
+/// Returns `len` pseudo-random bytes, which do not compress.
+pub fn random_data(len: usize) -> Vec<u8> {
+    let mut state: u32 = 98765;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
@@ -0,0 +1,133 @@
+use std::io;
+use ::Lz77Options;
+use codeword::CodeWordFormat;
+use huffman::{HuffmanWriter, Symbol};
+
+pub enum Token {
+    Literal,
+    Match(usize, usize),
+}
+
+/// Serializes tokens in the format selected by the options.
+pub enum TokenWriter {
+    CodeWords(CodeWordWriter),
+    Huffman(HuffmanWriter),
+}
+
+impl TokenWriter {
+    pub fn new(options: &Lz77Options) -> Self {
+        if options.huffman && !options.raw {
+            TokenWriter::Huffman(HuffmanWriter::new())
+        } else {
+            TokenWriter::CodeWords(CodeWordWriter::new(options))
+        }
+    }
+
+    /// Returns whether another literal only costs its own byte, rather than also opening a new
+    /// literal run.
+    pub fn has_literals(&self) -> bool {
+        match *self {
+            TokenWriter::CodeWords(ref w) => !w.literals.is_empty(),
+            TokenWriter::Huffman(_) => true,
+        }
+    }
+
+    pub fn write_token<W>(&mut self, out: &mut W, token: Token, next: u8) -> io::Result<()>
+        where W: io::Write
+    {
+        match *self {
+            TokenWriter::CodeWords(ref mut w) => w.write_token(out, token, next),
+            TokenWriter::Huffman(ref mut w) => {
+                if let Token::Match(distance, length) = token {
+                    w.push(Symbol::Match { length, distance });
+                }
+                w.push(Symbol::Literal(next));
+                if w.is_full() {
+                    w.write_block(out)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes everything collected so far, so that it can be decoded.
+    pub fn flush<W>(&mut self, out: &mut W) -> io::Result<()>
+        where W: io::Write
+    {
+        match *self {
+            TokenWriter::CodeWords(ref mut w) => w.flush_literals(out),
+            TokenWriter::Huffman(ref mut w) => w.write_block(out),
+        }
+    }
+
+    /// Writes everything collected so far followed by the end marker that terminates a stream
+    /// with a header.
+    pub fn write_end_marker<W>(&mut self, out: &mut W) -> io::Result<()>
+        where W: io::Write
+    {
+        match *self {
+            TokenWriter::CodeWords(ref mut w) => w.write_end_marker(out),
+            TokenWriter::Huffman(ref mut w) => w.write_end(out),
+        }
+    }
+}
+
+/// Serializes tokens as codewords.
+///
+/// Literals are collected into runs that share a single codeword, a match is written as its
+/// codeword followed by the next literal.
+pub struct CodeWordWriter {
+    format: CodeWordFormat,
+    max_literal_run: usize,
+    literals: Vec<u8>,
+}
+
+impl CodeWordWriter {
+    pub fn new(options: &Lz77Options) -> Self {
+        let max_literal_run = options.match_len_limit() + 1;
+        CodeWordWriter {
+            format: CodeWordFormat::new(options.window_bits, options.varint()),
+            max_literal_run,
+            literals: Vec::with_capacity(max_literal_run),
+        }
+    }
+
+    pub fn write_token<W>(&mut self, out: &mut W, token: Token, next: u8) -> io::Result<()>
+        where W: io::Write
+    {
+        if self.literals.len() == self.max_literal_run {
+            self.flush_literals(out)?;
+        }
+
+        match token {
+            Token::Match(dist, len) => {
+                self.flush_literals(out)?;
+                self.format.write(out, dist, len)?;
+                out.write_all(&[next])?;
+            }
+            Token::Literal => {
+                self.literals.push(next);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush_literals<W>(&mut self, out: &mut W) -> io::Result<()>
+        where W: io::Write
+    {
+        if !self.literals.is_empty() {
+            self.format.write(out, 0, self.literals.len() - 1)?;
+            out.write_all(&self.literals[..])?;
+            self.literals.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the end marker, a match of length zero, that terminates a stream with a header.
+    pub fn write_end_marker<W>(&mut self, out: &mut W) -> io::Result<()>
+        where W: io::Write
+    {
+        self.flush_literals(out)?;
+        self.format.write(out, 1, 0)
+    }
+}